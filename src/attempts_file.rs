@@ -0,0 +1,79 @@
+//! Keeps the failed attempt count in `attempts_file` so that restarting the
+//! locker, e.g. after it was killed, doesn't reset brute-force tracking.
+//!
+//! The file holds the count as a decimal number. A missing file counts as no
+//! failed attempts, an unreadable one is logged and counts as none too.
+
+use std::path::Path;
+
+use log::error;
+
+pub fn load(path: &Path) -> u32 {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => contents.trim().parse().unwrap_or_else(|err| {
+            error!(
+                "Ignoring invalid attempts file {} with error {err}",
+                path.display()
+            );
+            0
+        }),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+        Err(err) => {
+            error!(
+                "Failed to read attempts file {} with error {err}",
+                path.display()
+            );
+            0
+        }
+    }
+}
+
+pub fn save(path: &Path, failed_attempts: u32) {
+    if let Err(err) = std::fs::write(path, format!("{failed_attempts}\n")) {
+        error!(
+            "Failed to write attempts file {} with error {err}",
+            path.display()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("waylockrs-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn missing_file_is_no_attempts() {
+        assert_eq!(load(&temp_path("missing")), 0);
+    }
+
+    #[test]
+    fn saved_count_is_loaded() {
+        let path = temp_path("saved");
+
+        save(&path, 7);
+        let loaded = load(&path);
+        save(&path, 0);
+        let reset = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((loaded, reset), (7, 0));
+    }
+
+    #[test]
+    fn invalid_file_is_no_attempts() {
+        let path = temp_path("invalid");
+        std::fs::write(&path, "not a number").unwrap();
+
+        let loaded = load(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded, 0);
+    }
+}
@@ -0,0 +1,129 @@
+//! Security events for auditing: the lock being acquired, failed attempts,
+//! unlocks and failures to lock. Built with the `journald` feature they go to
+//! the systemd journal with structured fields, e.g. for
+//! `journalctl WAYLOCKRS_EVENT=failed_attempt`, otherwise through `log`.
+//! Nothing about the password is ever recorded.
+
+use log::Level;
+
+pub enum SecurityEvent {
+    Locked,
+    /// The count of failed attempts so far
+    FailedAttempt(u32),
+    /// How the session was unlocked, e.g. "authenticated"
+    Unlocked(&'static str),
+    LockFailed,
+    /// No lock surface got the keyboard focus back while locked
+    FocusLost,
+}
+
+impl SecurityEvent {
+    fn level(&self) -> Level {
+        match self {
+            SecurityEvent::Locked | SecurityEvent::Unlocked(_) => Level::Info,
+            SecurityEvent::FailedAttempt(_) | SecurityEvent::FocusLost => Level::Warn,
+            SecurityEvent::LockFailed => Level::Error,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            SecurityEvent::Locked => "Session locked".to_string(),
+            SecurityEvent::FailedAttempt(count) => {
+                format!("Authentication failed ({count} failed attempts)")
+            }
+            SecurityEvent::Unlocked(reason) => format!("Session unlocked ({reason})"),
+            SecurityEvent::LockFailed => "Failed to lock the session".to_string(),
+            SecurityEvent::FocusLost => {
+                "Lost the keyboard focus while locked, the compositor may be misbehaving"
+                    .to_string()
+            }
+        }
+    }
+}
+
+pub fn record(event: SecurityEvent) {
+    #[cfg(feature = "journald")]
+    match journal::send(&event) {
+        Ok(()) => return,
+        Err(err) => log::debug!("Failed to write to the journal with error {err}"),
+    }
+    log::log!(event.level(), "{}", event.message());
+}
+
+#[cfg(feature = "journald")]
+mod journal {
+    //! Minimal client of the journal's native protocol, see
+    //! systemd.journal-fields(7) and https://systemd.io/JOURNAL_NATIVE_PROTOCOL/
+
+    use std::os::unix::net::UnixDatagram;
+
+    use log::Level;
+
+    use super::SecurityEvent;
+
+    const SOCKET_PATH: &str = "/run/systemd/journal/socket";
+
+    /// Value of the `WAYLOCKRS_EVENT` field
+    fn name(event: &SecurityEvent) -> &'static str {
+        match event {
+            SecurityEvent::Locked => "locked",
+            SecurityEvent::FailedAttempt(_) => "failed_attempt",
+            SecurityEvent::Unlocked(_) => "unlocked",
+            SecurityEvent::LockFailed => "lock_failed",
+            SecurityEvent::FocusLost => "focus_lost",
+        }
+    }
+
+    /// Syslog priority of a log level
+    fn priority(level: Level) -> u8 {
+        match level {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+
+    fn append_field(payload: &mut Vec<u8>, key: &str, value: &str) {
+        payload.extend_from_slice(key.as_bytes());
+        if value.contains('\n') {
+            // Multi-line values are sent length-prefixed instead
+            payload.push(b'\n');
+            payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            payload.push(b'=');
+        }
+        payload.extend_from_slice(value.as_bytes());
+        payload.push(b'\n');
+    }
+
+    pub fn send(event: &SecurityEvent) -> std::io::Result<()> {
+        let mut payload = Vec::new();
+        append_field(&mut payload, "MESSAGE", &event.message());
+        append_field(
+            &mut payload,
+            "PRIORITY",
+            &priority(event.level()).to_string(),
+        );
+        append_field(&mut payload, "SYSLOG_IDENTIFIER", "waylockrs");
+        append_field(&mut payload, "WAYLOCKRS_EVENT", name(event));
+        match event {
+            SecurityEvent::FailedAttempt(count) => {
+                append_field(
+                    &mut payload,
+                    "WAYLOCKRS_FAILED_ATTEMPTS",
+                    &count.to_string(),
+                );
+            }
+            SecurityEvent::Unlocked(reason) => {
+                append_field(&mut payload, "WAYLOCKRS_UNLOCK_REASON", reason);
+            }
+            SecurityEvent::Locked | SecurityEvent::LockFailed | SecurityEvent::FocusLost => {}
+        }
+
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to(&payload, SOCKET_PATH)?;
+        Ok(())
+    }
+}
@@ -1,14 +1,30 @@
 use std::ffi::{CStr, CString};
+use std::process::{Command, Stdio};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use log::{debug, error};
 use pam_client::{Context, ErrorCode, Flag};
 use secstr::SecVec;
-use smithay_client_toolkit::reexports::{calloop::EventLoop, calloop::channel};
-use users::get_current_username;
+use smithay_client_toolkit::reexports::calloop::{
+    EventLoop, channel,
+    timer::{TimeoutAction, Timer},
+};
+use users::{get_current_username, get_effective_uid};
+
+use crate::config::{Config, ExternalAuthMode};
 
 const SERVICE_NAME: &str = "waylockrs";
 
+/// Answer of the auth thread
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AuthResult {
+    /// Whether the password submitted with Enter was accepted
+    Password(bool),
+    /// `external_auth_command` succeeded, either on Enter or when polled
+    External,
+}
+
 pub struct PasswordBuffer(SecVec<u8>);
 
 impl PasswordBuffer {
@@ -40,11 +56,35 @@ impl PasswordBuffer {
         Self::zeroize_string(data);
     }
 
+    /// Removes the last character, which may span several bytes
     pub fn backspace(&mut self) {
-        let og_len = self.0.unsecure().len();
-        if og_len != 0 {
-            self.0.resize(og_len - 1, 0);
+        let password = self.unsecure();
+        let len = password.char_indices().last().map_or(0, |(index, _)| index);
+        self.truncate(len);
+    }
+
+    /// Removes the last word and the whitespace after it, like Ctrl+W in a
+    /// shell
+    pub fn backspace_word(&mut self) {
+        let password = self.unsecure().trim_end();
+        let len = password
+            .trim_end_matches(|c: char| !c.is_whitespace())
+            .len();
+        self.truncate(len);
+    }
+
+    /// Zeroes the bytes past `len` before dropping them, as shrinking a
+    /// `SecVec` leaves them in its allocation
+    fn truncate(&mut self, len: usize) {
+        for b in &mut self.0.unsecure_mut()[len..] {
+            unsafe { std::ptr::write_volatile(b, 0) };
         }
+        self.0.resize(len, 0);
+    }
+
+    /// Empties the buffer, the dropped `SecVec` zeroing the password
+    pub fn clear(&mut self) {
+        drop(self.take());
     }
 
     pub fn unsecure(&self) -> &str {
@@ -84,55 +124,224 @@ impl pam_client::ConversationHandler for LockConversation {
     }
 }
 
-pub fn create_and_run_auth_loop() -> (channel::Sender<PasswordBuffer>, channel::Channel<bool>) {
-    struct AuthLoopState {
-        auth_res_send: channel::Sender<bool>,
-        main_closed: bool,
-        context: pam_client::Context<LockConversation>,
+/// Sets the PAM items and environment that stricter stacks (audit, policy
+/// modules) expect from a graphical session:
+///
+/// * `PAM_TTY`: `tty<N>` from `XDG_VTNR`, left unset outside a VT session
+/// * `PAM_RUSER`: the user running the lock, who requests the check
+/// * `PAM_XDISPLAY`: `DISPLAY`, if Xwayland is running
+/// * The environment variables listed in `pam_env` that are set
+fn set_pam_items<C: pam_client::ConversationHandler>(
+    context: &mut Context<C>,
+    requesting_user: &str,
+    pam_env: &[String],
+) {
+    // A display name isn't a tty, modules like pam_securetty would misread it
+    if let Ok(vt) = std::env::var("XDG_VTNR")
+        && let Err(err) = context.set_tty(Some(&format!("tty{vt}")))
+    {
+        error!("Failed to set PAM_TTY with {:?}", err);
+    }
+    if let Err(err) = context.set_ruser(Some(requesting_user)) {
+        error!("Failed to set PAM_RUSER with {:?}", err);
+    }
+    if let Ok(display) = std::env::var("DISPLAY")
+        && let Err(err) = context.set_xdisplay(Some(&display))
+    {
+        error!("Failed to set PAM_XDISPLAY with {:?}", err);
     }
+    for name in pam_env {
+        if let Ok(value) = std::env::var(name)
+            && let Err(err) = context.putenv(&format!("{name}={value}"))
+        {
+            error!("Failed to pass {name} to PAM with {:?}", err);
+        }
+    }
+}
+
+/// Runs `external_auth_command` through the shell, a zero exit status
+/// authorizes the unlock. The command is killed and fails after `timeout`, so
+/// a hung check can't stall the password behind it
+fn external_auth_succeeds(command: &str, timeout: Duration) -> bool {
+    let mut child = match Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            error!("Failed to run the external auth command with error {err}");
+            return false;
+        }
+    };
+    let deadline = Instant::now() + timeout;
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                debug!("External auth command exited with {status}");
+                return status.success();
+            }
+            Ok(None) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+            Ok(None) => {
+                error!("External auth command timed out after {timeout:?}, killing it");
+                break;
+            }
+            Err(err) => {
+                error!("Failed to wait for the external auth command with error {err}");
+                break;
+            }
+        }
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+    false
+}
+
+/// Drops an `auth_user` other than the current user unless running as root.
+/// `pam_unix` only checks another user's password for root, so no password
+/// could unlock otherwise
+pub fn check_auth_user(config: &mut Config) {
+    let Some(auth_user) = config.auth_user.as_deref() else {
+        return;
+    };
+    let current_user = get_current_username();
+    if current_user.as_deref().and_then(|user| user.to_str()) == Some(auth_user)
+        || get_effective_uid() == 0
+    {
+        return;
+    }
+    error!(
+        "auth_user '{auth_user}' is another user, whose password only root can check. Authenticating the current user instead"
+    );
+    config.auth_user = None;
+}
 
-    let username = get_current_username()
+/// Runs PAM for `auth_user` if set, otherwise for the current user. With
+/// `external_auth_command`, Enter first runs the command, then PAM only in the
+/// augment mode, and the command is polled every `external_auth_poll_secs`
+pub fn create_and_run_auth_loop(
+    config: &Config,
+) -> (
+    channel::Sender<PasswordBuffer>,
+    channel::Channel<AuthResult>,
+) {
+    let current_user = get_current_username()
         .expect("Failed to get username")
         .to_str()
         .expect("Failed to get non-unicode username")
         .to_string();
+    let username = config
+        .auth_user
+        .clone()
+        .unwrap_or_else(|| current_user.clone());
 
     let conversation = LockConversation { password: None };
-    let context = Context::new(
+    let mut context = Context::new(
         SERVICE_NAME,            // Service name, decides which policy is used (see `/etc/pam.d`)
         Some(username.as_str()), // Optional preset user name
         conversation,            // Handler for user interaction
     )
     .expect("Failed to initialize PAM context");
+    set_pam_items(&mut context, &current_user, &config.pam_env);
     debug!("Prepared to authenticate user '{}'", username);
 
+    let external_command = config.external_auth_command.clone();
+    let timeout = Duration::from_secs(config.external_auth_timeout_secs);
+    let replace_pam =
+        external_command.is_some() && config.external_auth_mode == ExternalAuthMode::Replace;
+    let poll = external_command
+        .clone()
+        .filter(|_| config.external_auth_poll_secs > 0)
+        .map(|command| {
+            let interval = Duration::from_secs(config.external_auth_poll_secs);
+            (interval, timeout, command)
+        });
+
+    run_auth_loop(poll, move |password| {
+        if let Some(command) = external_command.as_deref()
+            && external_auth_succeeds(command, timeout)
+        {
+            return AuthResult::External;
+        }
+        if replace_pam {
+            return AuthResult::Password(false);
+        }
+        context.conversation_mut().password = Some(password);
+        match context.authenticate(Flag::NONE) {
+            Ok(()) => AuthResult::Password(true),
+            Err(err) => {
+                error!("Pam authenticate failed with {:?}", err);
+                AuthResult::Password(false)
+            }
+        }
+    })
+}
+
+/// Password accepted by the insecure preview mode
+#[cfg(feature = "preview")]
+pub const PREVIEW_PASSWORD: &str = "preview";
+
+/// Auth loop for the insecure preview mode which compares against
+/// [`PREVIEW_PASSWORD`] instead of asking PAM
+#[cfg(feature = "preview")]
+pub fn create_preview_auth_loop() -> (
+    channel::Sender<PasswordBuffer>,
+    channel::Channel<AuthResult>,
+) {
+    run_auth_loop(None, |password| {
+        AuthResult::Password(password.unsecure() == PREVIEW_PASSWORD)
+    })
+}
+
+/// Runs `authenticate` on a separate thread for every password received, and
+/// the `poll` command at its interval, reporting only its successes
+fn run_auth_loop<F>(
+    poll: Option<(Duration, Duration, String)>,
+    mut authenticate: F,
+) -> (
+    channel::Sender<PasswordBuffer>,
+    channel::Channel<AuthResult>,
+)
+where
+    F: FnMut(PasswordBuffer) -> AuthResult + Send + 'static,
+{
+    struct AuthLoopState {
+        auth_res_send: channel::Sender<AuthResult>,
+        main_closed: bool,
+    }
+
     let (auth_req_send, auth_req_recv) = channel::channel::<PasswordBuffer>();
-    let (auth_res_send, auth_res_recv) = channel::channel::<bool>();
+    let (auth_res_send, auth_res_recv) = channel::channel::<AuthResult>();
 
     thread::spawn(move || {
         let mut event_loop: EventLoop<AuthLoopState> = EventLoop::try_new().unwrap();
         event_loop
             .handle()
-            .insert_source(auth_req_recv, |evt, _metadata, state| match evt {
+            .insert_source(auth_req_recv, move |evt, _metadata, state| match evt {
                 channel::Event::Msg(password) => {
-                    state.context.conversation_mut().password = Some(password);
-                    let status = match state.context.authenticate(Flag::NONE) {
-                        Ok(()) => true,
-                        Err(err) => {
-                            error!("Pam authenticate failed with {:?}", err);
-                            false
-                        }
-                    };
+                    let status = authenticate(password);
                     state.auth_res_send.send(status).unwrap();
                 }
                 channel::Event::Closed => state.main_closed = true,
             })
             .unwrap();
+        if let Some((interval, timeout, command)) = poll {
+            event_loop
+                .handle()
+                .insert_source(Timer::from_duration(interval), move |_, _, state| {
+                    // Kept polling after a success, which only unlocks once locked
+                    if external_auth_succeeds(&command, timeout) {
+                        state.auth_res_send.send(AuthResult::External).unwrap();
+                    }
+                    TimeoutAction::ToDuration(interval)
+                })
+                .unwrap();
+        }
 
         let mut state = AuthLoopState {
             auth_res_send,
             main_closed: false,
-            context,
         };
 
         while !state.main_closed {
@@ -144,3 +353,41 @@ pub fn create_and_run_auth_loop() -> (channel::Sender<PasswordBuffer>, channel::
 
     (auth_req_send, auth_res_recv)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(password: &str) -> PasswordBuffer {
+        let mut buffer = PasswordBuffer::new();
+        buffer.append(password.to_string());
+        buffer
+    }
+
+    #[test]
+    fn backspace_removes_a_whole_character() {
+        let mut password = buffer("pâté");
+        password.backspace();
+        assert_eq!(password.unsecure(), "pât");
+    }
+
+    #[test]
+    fn backspace_word_removes_the_last_word_and_trailing_spaces() {
+        let mut password = buffer("correct horse  ");
+        password.backspace_word();
+        assert_eq!(password.unsecure(), "correct ");
+        password.backspace_word();
+        assert_eq!(password.unsecure(), "");
+        password.backspace_word();
+        assert_eq!(password.unsecure(), "");
+    }
+
+    #[test]
+    fn external_auth_command_is_killed_after_the_timeout() {
+        let timeout = Duration::from_millis(100);
+        assert!(external_auth_succeeds("true", timeout));
+        let started = Instant::now();
+        assert!(!external_auth_succeeds("sleep 10", timeout));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
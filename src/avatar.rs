@@ -0,0 +1,50 @@
+//! Finds and loads the picture of the user shown with `show_avatar`
+
+use std::path::PathBuf;
+
+use log::{debug, error};
+use users::get_current_username;
+
+use crate::background_image::try_load_image;
+use crate::config::Config;
+
+/// Directory where AccountsService keeps the pictures set in user settings
+const ACCOUNTS_SERVICE_ICONS: &str = "/var/lib/AccountsService/icons";
+
+/// Loads `avatar.path`, otherwise the user's `~/.face` or AccountsService
+/// picture. None if there is no readable one
+pub fn load_avatar(config: &Config) -> Option<cairo::ImageSurface> {
+    let path = match config.avatar.path.as_ref() {
+        Some(path) => PathBuf::from(path),
+        None => find_avatar(config)?,
+    };
+    let path = path.to_str()?;
+    match try_load_image(path, config.max_image_pixels) {
+        Ok(surface) => Some(surface),
+        Err(err) => {
+            error!("Not showing avatar {path} with error {err}");
+            None
+        }
+    }
+}
+
+/// The avatar of `auth_user`, or of the current user
+fn find_avatar(config: &Config) -> Option<PathBuf> {
+    let username = match config.auth_user.clone() {
+        Some(username) => username,
+        None => get_current_username()?.to_str()?.to_string(),
+    };
+    let mut candidates = Vec::new();
+    // ~/.face belongs to the user running the lock, not another `auth_user`
+    if config.auth_user.is_none()
+        && let Some(home) = std::env::var_os("HOME")
+    {
+        candidates.push(PathBuf::from(home).join(".face"));
+    }
+    candidates.push(PathBuf::from(ACCOUNTS_SERVICE_ICONS).join(username));
+    let found = candidates.into_iter().find(|path| path.is_file());
+    if found.is_none() {
+        debug!("No avatar found, not showing one");
+    }
+    found
+}
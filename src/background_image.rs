@@ -1,12 +1,44 @@
-use crate::config::BackgroundMode;
+use image::error::{ImageError, LimitError, LimitErrorKind};
+use log::error;
 
-pub fn load_image(path: &str) -> cairo::ImageSurface {
-    let image = match image::open(&path) {
-        Ok(i) => i,
-        Err(e) => {
-            panic!("Failed to open image {path} with error {e:?}")
-        }
-    };
+use crate::config::{BackgroundFilter, BackgroundMode, Config};
+
+/// Loads the configured background image, or the first slideshow image,
+/// unless drawing a solid color or screenshots. Images that can't be
+/// decoded or are over `max_image_pixels` are skipped for the solid color
+pub fn load_configured_image(config: &Config) -> Option<cairo::ImageSurface> {
+    let path = configured_image_path(config)?;
+    try_load_image(path, config.max_image_pixels)
+        .inspect_err(|err| {
+            error!("Not loading image {path} with error {err}, using the background color")
+        })
+        .ok()
+}
+
+/// Path of the image shown first: the first slideshow image, else
+/// `background_image`. None when drawing a solid color or screenshots
+pub fn configured_image_path(config: &Config) -> Option<&str> {
+    if !config.background_mode.shows_images() {
+        return None;
+    }
+    config
+        .background_slideshow
+        .first()
+        .or(config.background_image.as_ref())
+        .map(String::as_str)
+}
+
+/// Decodes the image at `path`, refusing from its header alone images of more
+/// than `max_pixels` (0 = no limit) that could exhaust the memory
+pub fn try_load_image(path: &str, max_pixels: u64) -> Result<cairo::ImageSurface, ImageError> {
+    let (width, height) = image::image_dimensions(path)?;
+    if max_pixels > 0 && width as u64 * height as u64 > max_pixels {
+        return Err(ImageError::Limits(LimitError::from_kind(
+            LimitErrorKind::DimensionError,
+        )));
+    }
+
+    let image = image::open(&path)?;
 
     let image = image.to_rgba8();
 
@@ -32,13 +64,14 @@ pub fn load_image(path: &str) -> cairo::ImageSurface {
         }
     }
 
-    cairo_surface
+    Ok(cairo_surface)
 }
 
 pub fn render_background_image(
     context: &cairo::Context,
     image: &cairo::ImageSurface,
     mode: BackgroundMode,
+    filter: BackgroundFilter,
     buffer_width: i32,
     buffer_height: i32,
 ) {
@@ -52,7 +85,8 @@ pub fn render_background_image(
     context.save().unwrap();
 
     match mode {
-        BackgroundMode::Stretch => {
+        // Screenshots already have the size of the output
+        BackgroundMode::Stretch | BackgroundMode::Screenshot => {
             context.scale(width_ratio, height_ratio);
             context.set_source_surface(&image, 0.0, 0.0).unwrap();
         }
@@ -89,6 +123,12 @@ pub fn render_background_image(
         }
         BackgroundMode::SolidColor => {}
     };
+    context.source().set_filter(match filter {
+        BackgroundFilter::Fast => cairo::Filter::Fast,
+        BackgroundFilter::Good => cairo::Filter::Good,
+        BackgroundFilter::Best => cairo::Filter::Best,
+        BackgroundFilter::Nearest => cairo::Filter::Nearest,
+    });
     context.paint().unwrap();
     context.restore().unwrap();
 }
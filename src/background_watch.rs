@@ -0,0 +1,86 @@
+//! Reloads the background image when its file changes.
+//!
+//! The parent directory is watched rather than the file itself so that
+//! wallpaper tools replacing the file atomically (write then rename) are
+//! noticed too. A missing or unreadable file keeps the current image.
+
+use std::path::Path;
+
+use inotify::{Inotify, WatchMask};
+use log::error;
+use smithay_client_toolkit::reexports::calloop::{Interest, Mode, PostAction, generic::Generic};
+
+use crate::{State, background_image::try_load_image};
+
+impl State {
+    pub fn create_background_watch(&self) {
+        let Some(path) = self.config.background_image.clone() else {
+            return;
+        };
+        if !self.config.background_mode.shows_images() {
+            return;
+        }
+        let path = Path::new(&path);
+        let (Some(dir), Some(file_name)) = (path.parent(), path.file_name()) else {
+            return;
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let file_name = file_name.to_owned();
+
+        let inotify = match Inotify::init() {
+            Ok(inotify) => inotify,
+            Err(err) => {
+                error!("Failed to watch the background image with error {err}");
+                return;
+            }
+        };
+        let mask = WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE;
+        if let Err(err) = inotify.watches().add(dir, mask) {
+            error!("Failed to watch {} with error {err}", dir.display());
+            return;
+        }
+
+        let source = Generic::new(inotify, Interest::READ, Mode::Level);
+        let result = self
+            .loop_handle
+            .insert_source(source, move |_, inotify, state| {
+                let mut buffer = [0; 4096];
+                // SAFETY: the inotify fd is only read from, never closed or replaced
+                let events = unsafe { inotify.get_mut() }.read_events(&mut buffer);
+                let changed = match events {
+                    Ok(mut events) => events.any(|event| event.name == Some(file_name.as_os_str())),
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => false,
+                    Err(err) => {
+                        error!("Stopped watching the background image with error {err}");
+                        return Ok(PostAction::Remove);
+                    }
+                };
+                if changed {
+                    state.reload_background_image();
+                }
+                Ok(PostAction::Continue)
+            });
+        if let Err(err) = result {
+            error!("Failed to watch the background image with error {err}");
+        }
+    }
+
+    fn reload_background_image(&mut self) {
+        let Some(path) = self.config.background_image.as_deref() else {
+            return;
+        };
+        match try_load_image(path, self.config.max_image_pixels) {
+            Ok(image) => {
+                self.background_image = Some(image);
+                self.invalidate_backgrounds();
+            }
+            Err(err) => {
+                error!("Failed to reload {path} with error {err}, keeping the previous image")
+            }
+        }
+    }
+}
@@ -0,0 +1,88 @@
+//! Blur of image surfaces, approximating a gaussian with three box blurs.
+
+/// Blurs `surface` in place over about `radius` pixels
+pub fn blur(surface: &mut cairo::ImageSurface, radius: usize) {
+    let box_radius = radius / 2;
+    if box_radius == 0 {
+        return;
+    }
+    let (width, height) = (surface.width() as usize, surface.height() as usize);
+    let stride = surface.stride() as usize;
+    let Ok(mut data) = surface.data() else {
+        return;
+    };
+
+    let mut line = Vec::new();
+    let mut scratch = Vec::new();
+    for _ in 0..3 {
+        for y in 0..height {
+            let row = &mut data[y * stride..][..width * 4];
+            line.clear();
+            line.extend(row.chunks_exact(4).map(|p| [p[0], p[1], p[2], p[3]]));
+            box_blur(&mut line, box_radius, &mut scratch);
+            row.copy_from_slice(line.as_flattened());
+        }
+        for x in 0..width {
+            line.clear();
+            line.extend((0..height).map(|y| {
+                let p = &data[y * stride + x * 4..][..4];
+                [p[0], p[1], p[2], p[3]]
+            }));
+            box_blur(&mut line, box_radius, &mut scratch);
+            for (y, pixel) in line.iter().enumerate() {
+                data[y * stride + x * 4..][..4].copy_from_slice(pixel);
+            }
+        }
+    }
+}
+
+/// Averages every pixel of `pixels` with the `radius` pixels on each side,
+/// repeating the edge pixels past the ends. Channels are blurred separately,
+/// which is right for the premultiplied alpha of cairo surfaces
+fn box_blur(pixels: &mut [[u8; 4]], radius: usize, scratch: &mut Vec<[u8; 4]>) {
+    let Some(last) = pixels.len().checked_sub(1) else {
+        return;
+    };
+    scratch.clear();
+    scratch.extend_from_slice(pixels);
+    let at = |i: isize| scratch[i.clamp(0, last as isize) as usize];
+    let (radius, window) = (radius as isize, 2 * radius as u32 + 1);
+
+    let mut sums = [0u32; 4];
+    for i in -radius..=radius {
+        for (sum, channel) in sums.iter_mut().zip(at(i)) {
+            *sum += channel as u32;
+        }
+    }
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let i = i as isize;
+        for (channel, sum) in pixel.iter_mut().zip(sums) {
+            *channel = ((sum + window / 2) / window) as u8;
+        }
+        let (added, removed) = (at(i + radius + 1), at(i - radius));
+        for ((sum, added), removed) in sums.iter_mut().zip(added).zip(removed) {
+            *sum = *sum + added as u32 - removed as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uniform_pixels_are_unchanged() {
+        let mut pixels = vec![[10, 20, 30, 255]; 8];
+        box_blur(&mut pixels, 3, &mut Vec::new());
+        assert_eq!(pixels, vec![[10, 20, 30, 255]; 8]);
+    }
+
+    #[test]
+    fn edges_are_softened() {
+        let mut pixels = vec![[0, 0, 0, 0]; 4];
+        pixels.extend(vec![[255, 255, 255, 255]; 4]);
+        box_blur(&mut pixels, 1, &mut Vec::new());
+        let alphas = pixels.iter().map(|p| p[3]).collect::<Vec<_>>();
+        assert_eq!(alphas, vec![0, 0, 0, 85, 170, 255, 255, 255]);
+    }
+}
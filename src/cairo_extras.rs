@@ -1,11 +1,167 @@
 use crate::config;
 
+/// Fontconfig generic family that resolves to the system's color emoji font
+const EMOJI_FONT_FAMILY: &str = "emoji";
+
+/// Point of a text's box that is placed at the coordinates given to the
+/// anchored text helpers. The box spans the text's advance horizontally and
+/// the font's ascent + descent vertically.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TextAnchor {
+    TopLeft,
+    Top,
+    Center,
+}
+
+impl TextAnchor {
+    /// Fractions of the box width and height that lie left of and above the anchor
+    fn fractions(self) -> (f64, f64) {
+        match self {
+            TextAnchor::TopLeft => (0.0, 0.0),
+            TextAnchor::Top => (0.5, 0.0),
+            TextAnchor::Center => (0.5, 0.5),
+        }
+    }
+}
+
 pub trait CairoExtras {
     fn set_source_color(&self, color: &config::Color);
+    fn rounded_rectangle(&self, x: f64, y: f64, width: f64, height: f64, radius: f64);
+    fn text_bounds_anchored(
+        &self,
+        text: &str,
+        x: f64,
+        y: f64,
+        anchor: TextAnchor,
+    ) -> cairo::Rectangle;
+    fn draw_text_anchored(&self, text: &str, x: f64, y: f64, anchor: TextAnchor);
+    fn text_path_anchored(&self, text: &str, x: f64, y: f64, anchor: TextAnchor);
 }
 
 impl CairoExtras for cairo::Context {
     fn set_source_color(&self, color: &config::Color) {
         self.set_source_rgba(color.red, color.green, color.blue, color.alpha);
     }
+
+    /// Adds a closed rectangle path with corners rounded by `radius`. The
+    /// radius is clamped so opposing corners never overlap.
+    fn rounded_rectangle(&self, x: f64, y: f64, width: f64, height: f64, radius: f64) {
+        use std::f64::consts::PI;
+
+        let radius = radius.min(width / 2.0).min(height / 2.0);
+        if radius <= 0.0 {
+            self.rectangle(x, y, width, height);
+            return;
+        }
+
+        self.new_sub_path();
+        self.arc(x + width - radius, y + radius, radius, -PI / 2.0, 0.0);
+        self.arc(
+            x + width - radius,
+            y + height - radius,
+            radius,
+            0.0,
+            PI / 2.0,
+        );
+        self.arc(x + radius, y + height - radius, radius, PI / 2.0, PI);
+        self.arc(x + radius, y + radius, radius, PI, 3.0 * PI / 2.0);
+        self.close_path();
+    }
+
+    /// Box that `text` would occupy with the current font when anchored at (x, y)
+    fn text_bounds_anchored(
+        &self,
+        text: &str,
+        x: f64,
+        y: f64,
+        anchor: TextAnchor,
+    ) -> cairo::Rectangle {
+        let font_extents = self.font_extents().unwrap();
+        let width = text_runs(text)
+            .map(|(run, is_emoji)| with_run_font(self, is_emoji, || self.text_extents(run)))
+            .map(|extents| extents.unwrap().x_advance())
+            .sum();
+        let height = font_extents.ascent() + font_extents.descent();
+        let (fx, fy) = anchor.fractions();
+        cairo::Rectangle::new(x - width * fx, y - height * fy, width, height)
+    }
+
+    /// Shows `text` with the current font and source, anchored at (x, y). Emoji
+    /// are shown with the system's color emoji font instead of as tofu.
+    fn draw_text_anchored(&self, text: &str, x: f64, y: f64, anchor: TextAnchor) {
+        move_to_text_origin(self, text, x, y, anchor);
+        for (run, is_emoji) in text_runs(text) {
+            with_run_font(self, is_emoji, || self.show_text(run)).unwrap();
+        }
+        self.close_path();
+        self.new_sub_path();
+    }
+
+    /// Adds the outline of `text` anchored at (x, y) to the current path, for
+    /// callers that want to fill and stroke it themselves. Emoji are outlined
+    /// from the emoji font, as `draw_text_anchored` shows them
+    fn text_path_anchored(&self, text: &str, x: f64, y: f64, anchor: TextAnchor) {
+        move_to_text_origin(self, text, x, y, anchor);
+        for (run, is_emoji) in text_runs(text) {
+            with_run_font(self, is_emoji, || self.text_path(run));
+        }
+    }
+}
+
+fn move_to_text_origin(context: &cairo::Context, text: &str, x: f64, y: f64, anchor: TextAnchor) {
+    let bounds = context.text_bounds_anchored(text, x, y, anchor);
+    let ascent = context.font_extents().unwrap().ascent();
+    context.move_to(bounds.x(), bounds.y() + ascent);
+}
+
+/// Whether `c` should be drawn from the emoji font rather than the text font
+fn is_emoji(c: char) -> bool {
+    matches!(c as u32,
+        0x1F000..=0x1FAFF // pictographs, emoticons, transport, flags, supplemental symbols
+        | 0x2600..=0x27BF // miscellaneous symbols and dingbats
+        | 0x2B00..=0x2BFF // arrows and stars like ⭐
+        | 0xE0020..=0xE007F // tag sequences for subdivision flags
+    )
+}
+
+/// Characters that never start an emoji run but continue one, such as the
+/// zero width joiner and the emoji variation selector
+fn is_emoji_continuation(c: char) -> bool {
+    matches!(c as u32, 0x200D | 0xFE0F | 0x20E3)
+}
+
+/// Splits `text` into consecutive runs, flagging which ones are emoji
+fn text_runs(text: &str) -> impl Iterator<Item = (&str, bool)> {
+    let mut rest = text;
+    std::iter::from_fn(move || {
+        let first = rest.chars().next()?;
+        let is_emoji_run = is_emoji(first);
+        let end = rest
+            .char_indices()
+            .skip(1)
+            .find(|&(_, c)| {
+                let continues = is_emoji(c) || (is_emoji_run && is_emoji_continuation(c));
+                continues != is_emoji_run
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (run, tail) = rest.split_at(end);
+        rest = tail;
+        Some((run, is_emoji_run))
+    })
+}
+
+/// Runs `f` with the emoji font selected for emoji runs, keeping the size
+fn with_run_font<T>(context: &cairo::Context, is_emoji: bool, f: impl FnOnce() -> T) -> T {
+    if !is_emoji {
+        return f();
+    }
+    context.save().unwrap();
+    context.select_font_face(
+        EMOJI_FONT_FAMILY,
+        cairo::FontSlant::Normal,
+        cairo::FontWeight::Normal,
+    );
+    let result = f();
+    context.restore().unwrap();
+    result
 }
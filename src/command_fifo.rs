@@ -0,0 +1,148 @@
+//! Runs commands written as lines to a named pipe, for scripts:
+//!
+//! * `clear` empties the typed password
+//! * `reload` reloads the theme, like SIGUSR2 or SIGHUP
+//! * `message <text>` shows the text in the indicator, `message` alone hides it
+//!
+//! There is deliberately no `unlock`: the pipe can't tell who wrote to it, so
+//! it would bypass authentication. The pipe is only accessible to the user and
+//! removed when exiting.
+
+use std::fs::{File, OpenOptions};
+use std::io::Read;
+use std::os::fd::{AsFd, BorrowedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::path::PathBuf;
+
+use log::{debug, error, warn};
+use smithay_client_toolkit::reexports::calloop::{Interest, Mode, PostAction, generic::Generic};
+
+use crate::State;
+
+/// Longest command accepted, longer lines are dropped
+const MAX_LINE: usize = 1024;
+
+struct CommandFifo {
+    file: File,
+    path: PathBuf,
+}
+
+impl AsFd for CommandFifo {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl Drop for CommandFifo {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+impl State {
+    pub fn create_command_fifo(&self) {
+        let Some(path) = self.config.command_fifo.clone() else {
+            return;
+        };
+        // A previous instance that crashed may have left its pipe behind,
+        // anything else at the path is not ours to remove
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_fifo() => {
+                if let Err(err) = std::fs::remove_file(&path) {
+                    error!("Failed to remove the stale command FIFO {path} with error {err}");
+                    return;
+                }
+            }
+            Ok(_) => {
+                error!("Not creating the command FIFO {path} over an existing file");
+                return;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                error!("Failed to check the command FIFO path {path} with error {err}");
+                return;
+            }
+        }
+        let c_path =
+            match std::ffi::CString::new(std::path::Path::new(&path).as_os_str().as_bytes()) {
+                Ok(c_path) => c_path,
+                Err(err) => {
+                    error!("Invalid command FIFO path {path}: {err}");
+                    return;
+                }
+            };
+        if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+            let err = std::io::Error::last_os_error();
+            error!("Failed to create the command FIFO {path} with error {err}");
+            return;
+        }
+        // Also opened for writing so the pipe never reports the end of file
+        // once a writer closes it
+        let file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(&path)
+        {
+            Ok(file) => file,
+            Err(err) => {
+                error!("Failed to open the command FIFO {path} with error {err}");
+                let _ = std::fs::remove_file(&path);
+                return;
+            }
+        };
+
+        let fifo = CommandFifo {
+            file,
+            path: PathBuf::from(&path),
+        };
+        let mut pending = Vec::new();
+        let source = Generic::new(fifo, Interest::READ, Mode::Level);
+        let result = self
+            .loop_handle
+            .insert_source(source, move |_, fifo, state| {
+                let mut buffer = [0; 4096];
+                loop {
+                    // SAFETY: the pipe is only read from, never closed or replaced
+                    match unsafe { fifo.get_mut() }.file.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(len) => pending.extend_from_slice(&buffer[..len]),
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            error!("Stopped reading the command FIFO with error {err}");
+                            return Ok(PostAction::Remove);
+                        }
+                    }
+                }
+                while let Some(end) = pending.iter().position(|b| *b == b'\n') {
+                    let line = pending.drain(..=end).collect::<Vec<_>>();
+                    state.run_command(String::from_utf8_lossy(&line).trim());
+                }
+                if pending.len() > MAX_LINE {
+                    warn!("Dropping an overlong line from the command FIFO");
+                    pending.clear();
+                }
+                Ok(PostAction::Continue)
+            });
+        if let Err(err) = result {
+            error!("Failed to watch the command FIFO {path} with error {err}");
+        }
+    }
+
+    fn run_command(&mut self, line: &str) {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        debug!("Received '{command}' on the command FIFO");
+        self.redraw_requested = true;
+        match command {
+            "" => {}
+            "clear" => self.clear_input(),
+            "reload" => self.reload_theme(),
+            "message" => {
+                let message = argument.trim();
+                self.indicator.message = (!message.is_empty()).then(|| message.to_string());
+            }
+            _ => warn!("Unknown command '{command}' on the command FIFO"),
+        }
+    }
+}
@@ -1,5 +1,8 @@
 use core::fmt;
-use std::{ffi::OsString, num::ParseIntError, str::FromStr};
+use std::{
+    cell::Cell, collections::HashMap, ffi::OsString, num::ParseIntError, str::FromStr,
+    time::Duration,
+};
 
 use lexopt::ValueExt;
 use serde::{Deserialize, Serialize};
@@ -15,6 +18,213 @@ pub enum BackgroundMode {
     Center,
     Tile,
     SolidColor,
+    /// The screen as it was before locking, blurred by `blur_sigma`. Needs
+    /// wlr-screencopy
+    Screenshot,
+}
+
+impl BackgroundMode {
+    /// Whether `background_image` and the slideshow are shown
+    pub fn shows_images(self) -> bool {
+        !matches!(
+            self,
+            BackgroundMode::SolidColor | BackgroundMode::Screenshot
+        )
+    }
+}
+
+/// Cairo filter used when scaling the background image
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackgroundFilter {
+    Fast,
+    Good,
+    Best,
+    /// No interpolation, keeps pixel art sharp
+    Nearest,
+}
+
+/// Kinds of input that count as activity and wake the indicator
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActivitySource {
+    /// Keys that edit or submit the password
+    Key,
+    /// Keys that don't produce input, like modifiers
+    Modifier,
+    Pointer,
+    Touch,
+}
+
+/// How `external_auth_command` combines with the password check
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExternalAuthMode {
+    /// Either the command or the password unlocks
+    Augment,
+    /// Only the command unlocks, PAM is never asked
+    Replace,
+}
+
+/// What the Escape key does
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscapeAction {
+    /// Empties the typed password
+    Clear,
+    Ignore,
+    /// Closes `--preview`. Ignored while actually locked
+    Quit,
+}
+
+/// Elements that can be listed in `overlay.order`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OverlayElement {
+    Clock,
+    Indicator,
+    Modifiers,
+    Avatar,
+}
+
+/// Modifiers that `show_modifiers` can show as badges. Scroll Lock isn't
+/// one, as Wayland doesn't report it to clients
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+    Super,
+    CapsLock,
+    NumLock,
+}
+
+/// Outputs whose indicator shows the highlight of each key press
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedbackOn {
+    All,
+    /// Only the output whose lock surface has keyboard focus
+    Focused,
+}
+
+/// Indicator state drawn by `--render-to-png`
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderState {
+    Idle,
+    Typing,
+    Verifying,
+    Wrong,
+    Cleared,
+}
+
+/// How the overlay elements are positioned
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    /// Every element at its own position: clock centered, ring below
+    Free,
+    /// Elements packed as a column centered on the surface
+    Stacked,
+}
+
+/// How configured font sizes relate to the output scale
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FontScaling {
+    /// Sizes are in surface pixels, whatever the output scale
+    Logical,
+    /// Sizes are multiplied by the output scale, keeping text the same
+    /// physical size on every monitor
+    Physical,
+}
+
+/// Which lock surfaces draw the clock and indicator
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShowOn {
+    All,
+    Primary,
+    /// The clock everywhere, the indicator only on the primary output
+    ClockOnSecondary,
+}
+
+/// Position of the clock along one axis
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClockPosition {
+    /// Against the left or top edge, "left", "top" or "start"
+    Start,
+    Center,
+    /// Against the right or bottom edge, "right", "bottom" or "end"
+    End,
+    /// Pixels from the center, towards the right or bottom when positive
+    Offset(f64),
+}
+
+impl<'de> Deserialize<'de> for ClockPosition {
+    fn deserialize<D>(deserializer: D) -> Result<ClockPosition, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct PositionVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for PositionVisitor {
+            type Value = ClockPosition;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str(
+                    "a number of pixels, \"top\", \"bottom\", \"left\", \"right\" or \"center\"",
+                )
+            }
+
+            fn visit_i64<E>(self, v: i64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(ClockPosition::Offset(v as f64))
+            }
+
+            fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                Ok(ClockPosition::Offset(v))
+            }
+
+            // Numbers given as CLI arguments arrive as strings
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E>
+            where
+                E: serde::de::Error,
+            {
+                match v {
+                    "left" | "top" | "start" => Ok(ClockPosition::Start),
+                    "center" => Ok(ClockPosition::Center),
+                    "right" | "bottom" | "end" => Ok(ClockPosition::End),
+                    _ => v
+                        .parse()
+                        .map(ClockPosition::Offset)
+                        .map_err(|_| E::invalid_value(serde::de::Unexpected::Str(v), &self)),
+                }
+            }
+        }
+        deserializer.deserialize_any(PositionVisitor)
+    }
+}
+
+impl Serialize for ClockPosition {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        match self {
+            ClockPosition::Start => serializer.serialize_str("start"),
+            ClockPosition::Center => serializer.serialize_str("center"),
+            ClockPosition::End => serializer.serialize_str("end"),
+            ClockPosition::Offset(offset) => serializer.serialize_f64(*offset),
+        }
+    }
 }
 
 fn parse_int(value: &str) -> Result<i64, ParseIntError> {
@@ -24,6 +234,12 @@ fn parse_int(value: &str) -> Result<i64, ParseIntError> {
     }
 }
 
+thread_local! {
+    /// Set when a color fails to parse. toml keeps only the message of custom
+    /// errors, so this tells `ConfigError` what failed without reading it
+    static BAD_COLOR: Cell<bool> = const { Cell::new(false) };
+}
+
 #[derive(Clone, Debug)]
 pub struct Color {
     pub red: f64,
@@ -73,9 +289,9 @@ impl<'de> Deserialize<'de> for Color {
                 {
                     Ok((u32_val as u32) << 8 | 0xFF)
                 } else {
+                    BAD_COLOR.set(true);
                     Err(serde::de::Error::custom(format!(
-                        "Invalid color. Please use a 0xRRGGBBAA value {:?}",
-                        v
+                        "Invalid color {v:?}, please use a 0xRRGGBBAA or RRGGBB value"
                     )))
                 }
             }
@@ -83,10 +299,10 @@ impl<'de> Deserialize<'de> for Color {
         let u32_val: u32 = deserializer.deserialize_u32(U32Visitor)?;
         let bytes: [u8; 4] = u32_val.to_be_bytes();
         Ok(Color {
-            red: (bytes[0] as f64 / 256.0),
-            green: (bytes[1] as f64 / 256.0),
-            blue: (bytes[2] as f64 / 256.0),
-            alpha: (bytes[3] as f64 / 256.0),
+            red: (bytes[0] as f64 / 255.0),
+            green: (bytes[1] as f64 / 255.0),
+            blue: (bytes[2] as f64 / 255.0),
+            alpha: (bytes[3] as f64 / 255.0),
         })
     }
 }
@@ -97,10 +313,10 @@ impl Serialize for Color {
         S: serde::Serializer,
     {
         let bytes: [u8; 4] = [
-            (self.red * 256.0).round().clamp(0.0, 255.0) as u8,
-            (self.green * 256.0).round().clamp(0.0, 255.0) as u8,
-            (self.blue * 256.0).round().clamp(0.0, 255.0) as u8,
-            (self.alpha * 256.0).round().clamp(0.0, 255.0) as u8,
+            (self.red * 255.0).round().clamp(0.0, 255.0) as u8,
+            (self.green * 255.0).round().clamp(0.0, 255.0) as u8,
+            (self.blue * 255.0).round().clamp(0.0, 255.0) as u8,
+            (self.alpha * 255.0).round().clamp(0.0, 255.0) as u8,
         ];
         let u32_val: u32 = u32::from_be_bytes(bytes);
         let u32_str = format!("{:#010X}", u32_val);
@@ -122,11 +338,148 @@ pub struct ColorSet {
 #[serde(deny_unknown_fields)]
 pub struct Clock {
     pub show_seconds: bool,
+    pub pad_hours: bool,
+    pub use_12h: bool,
+    pub format: String,
+    pub show_date: bool,
+    pub date_format: String,
+    pub date_font_size: f64,
+    pub x: ClockPosition,
+    pub y: ClockPosition,
     pub font: String,
     pub font_size: f64,
     pub text_color: Color,
+    pub caps_lock_tint: bool,
+    pub caps_lock_color: Color,
     pub outline_color: Color,
     pub outline_width: f64,
+    pub timezone: Option<String>,
+    pub use_utc: bool,
+    pub fallback_text: String,
+    pub extra_zones: Vec<String>,
+    pub extra_zones_font_size: f64,
+    pub extra_zones_gap: f64,
+    pub backdrop_blur: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Overlay {
+    /// Elements to draw, bottom first; unlisted ones are hidden
+    pub order: Vec<OverlayElement>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Avatar {
+    pub path: Option<String>,
+    pub size: f64,
+    pub circular: bool,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Modifiers {
+    /// Modifiers getting a badge while active, in this order
+    pub tracked: Vec<Modifier>,
+    pub font: String,
+    pub font_size: f64,
+    pub text_color: Color,
+    pub badge_color: Color,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OverlayCard {
+    pub enabled: bool,
+    pub color: Color,
+    pub padding: f64,
+    pub corner_radius: f64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EscalateObscure {
+    pub enabled: bool,
+    pub step: f64,
+    pub max: f64,
+}
+
+/// Background of one output, overriding `background_image`
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputBackground {
+    /// Name or description of the output
+    pub name: String,
+    pub image: String,
+    pub mode: Option<BackgroundMode>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Border {
+    pub width: f64,
+    pub color: Color,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct BackgroundTint {
+    pub enabled: bool,
+    pub verifying: Color,
+    pub wrong: Color,
+    pub cleared: Color,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Keyboard {
+    pub repeat_delay_ms: i32,
+    pub repeat_rate: i32,
+    pub keysym_fallback: bool,
+    /// Labels replacing layout names, keyed by xkb name or layout index
+    pub layout_names: HashMap<String, String>,
+}
+
+impl Keyboard {
+    /// Repeat settings replacing the compositor's, if any are configured
+    pub fn repeat_override(&self) -> Option<(Duration, u32)> {
+        if self.repeat_delay_ms < 0 && self.repeat_rate < 0 {
+            return None;
+        }
+        let delay = if self.repeat_delay_ms < 0 {
+            600
+        } else {
+            self.repeat_delay_ms as u64
+        };
+        let rate = if self.repeat_rate < 0 {
+            25
+        } else {
+            self.repeat_rate as u32
+        };
+        Some((Duration::from_millis(delay), rate))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Render {
+    pub single_surface: bool,
+    pub dither: Dither,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dither {
+    pub enabled: bool,
+    pub bits: u32,
+}
+
+impl Dither {
+    /// Bits per color channel to dither to, if enabled
+    pub fn bits(&self) -> Option<u32> {
+        self.enabled.then_some(self.bits)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -145,6 +498,15 @@ pub struct IndicatorHighlights {
     pub key: Color,
     pub caps_lock_backspace: Color,
     pub caps_lock_key: Color,
+    pub verifying: Option<Color>,
+    pub wrong: Option<Color>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HueCycle {
+    pub degrees: f64,
+    pub period_secs: f64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -152,16 +514,39 @@ pub struct IndicatorHighlights {
 pub struct Indicator {
     pub colors: IndicatorColors,
     pub highlights: IndicatorHighlights,
+    pub hue_cycle: HueCycle,
     pub radius: f64,
     pub thickness: f64,
+    pub highlight_radius: f64,
+    pub spinner_speed: f64,
+    pub spinner_gap_degrees: f64,
+    pub show_borders: bool,
+    pub border_width: f64,
     pub font: String,
     pub font_size: f64,
+    pub layout_font: Option<String>,
+    pub layout_font_size: f64,
     pub show_caps_lock_indicator: bool,
     pub show_caps_lock_text: bool,
+    pub caps_lock_box: bool,
+    pub show_no_keyboard_text: bool,
+    pub show_initializing_text: bool,
+    pub reveal_last_char: bool,
+    pub show_password_dots: bool,
+    pub max_password_dots: usize,
+    pub show_failure_text: bool,
+    pub auth_prompt_text: String,
     pub hide_keyboard_layout: bool,
     pub show_text: bool,
     pub show_even_if_idle: bool,
+    pub show_on_lock_secs: u64,
+    pub min_state_display_ms: u64,
+    pub feedback_on: FeedbackOn,
+    pub jitter: f64,
     pub show_failed_attempts: bool,
+    pub attempts_display_timeout_secs: u64,
+    pub reset_attempts_on_success: bool,
+    pub corner_radius: f64,
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -170,17 +555,107 @@ pub struct Config {
     pub background_color: Color,
     pub background_image: Option<String>,
     pub background_mode: BackgroundMode,
+    pub background_filter: BackgroundFilter,
+    pub watch_background: bool,
+    pub background_slideshow: Vec<String>,
+    pub output: Vec<OutputBackground>,
+    pub max_image_pixels: u64,
+    pub blur_sigma: f64,
+    pub slideshow_interval_secs: u64,
+    pub slideshow_crossfade_ms: u64,
+    pub dim: f64,
+    pub dim_color: Color,
     pub clock: Clock,
     pub indicator: Indicator,
+    pub overlay: Overlay,
+    pub layout: Layout,
+    pub layout_spacing: f64,
+    pub font_scaling: FontScaling,
+    pub overlay_card: OverlayCard,
+    pub modifiers: Modifiers,
+    pub avatar: Avatar,
+    pub escalate_obscure_on_failure: EscalateObscure,
+    pub background_tint_on_state: BackgroundTint,
+    pub border: Border,
+    pub keyboard: Keyboard,
+    pub render: Render,
     pub ignore_empty_password: bool,
+    pub auto_submit_length: u32,
+    pub escape_action: EscapeAction,
+    pub max_auth_attempts: u32,
+    pub lockout_secs: u64,
+    pub backoff_base_secs: u64,
+    pub backoff_max_secs: u64,
+    pub backoff_after_attempts: u32,
+    pub attempts_file: Option<String>,
+    pub fade_out_ms: u64,
     pub show_clock: bool,
     pub show_indicator: bool,
+    pub show_modifiers: bool,
+    pub show_avatar: bool,
+    pub cold_start: bool,
+    pub show_pin_pad: bool,
+    pub pin_pad_fallback: bool,
     pub ready_fd: i32,
     pub daemonize: bool,
+    pub hide_cursor: bool,
+    pub primary_output: Option<String>,
+    pub show_on: ShowOn,
+    pub primary_follows_click: bool,
+    pub warn_on_focus_loss: bool,
+    pub output_debounce_ms: u64,
+    pub max_outputs: usize,
+    pub activity_sources: Vec<ActivitySource>,
+    pub status_socket_path: Option<String>,
+    pub command_fifo: Option<String>,
+    pub pam_env: Vec<String>,
+    pub auth_user: Option<String>,
+    pub external_auth_command: Option<String>,
+    pub external_auth_mode: ExternalAuthMode,
+    pub external_auth_poll_secs: u64,
+    pub external_auth_timeout_secs: u64,
+    pub profile: Option<String>,
 
     /// Workaround for CLI help as our Config loads the CLI flags
     #[serde(alias = "help", skip_serializing)]
     pub show_help: bool,
+
+    /// Insecure theming preview, only honored with the 'preview' build feature
+    #[serde(skip_serializing)]
+    pub preview: bool,
+
+    /// Offline rendering to a PNG instead of locking
+    #[serde(skip_serializing)]
+    pub render_to_png: Option<String>,
+    #[serde(skip_serializing)]
+    pub width: u32,
+    #[serde(skip_serializing)]
+    pub height: u32,
+    #[serde(skip_serializing)]
+    pub render_state: RenderState,
+}
+
+/// Merges `provided` over `orig`, recursing into tables present in both
+fn merge_table(orig: &toml::Table, provided: &toml::Table) -> toml::Table {
+    let mut result = toml::Table::new();
+    for key in orig.keys() {
+        if let Some(toml::Value::Table(orig_table)) = orig.get(key)
+            && let Some(toml::Value::Table(provided_table)) = provided.get(key)
+        {
+            let new_table = merge_table(orig_table, provided_table);
+            result.insert(key.clone(), toml::Value::Table(new_table));
+        } else if let Some(provided_value) = provided.get(key) {
+            result.insert(key.clone(), provided_value.clone());
+        } else {
+            result.insert(key.clone(), orig[key].clone());
+        }
+    }
+    for key in provided.keys() {
+        if !result.contains_key(key) {
+            result.insert(key.clone(), provided[key].clone());
+        }
+    }
+    result
 }
 
 /// Returns all long form arguments with their specified value or "true"
@@ -229,47 +704,135 @@ impl Iterator for ConfigArgsIter {
     }
 }
 
+/// Why the config could not be loaded
+#[derive(Debug)]
+pub enum ConfigError {
+    /// `config.toml` is not valid TOML
+    Syntax(toml::de::Error),
+    /// A key that no section of the config defines, usually a typo
+    UnknownField(toml::de::Error),
+    /// A color that is not a `0xRRGGBBAA` or `RRGGBB` value
+    BadColor(toml::de::Error),
+    /// A value of the wrong type, or not one of the allowed names
+    InvalidValue(toml::de::Error),
+    /// A CLI argument, or the profile it picks, that cannot be applied
+    BadArgument(lexopt::Error),
+}
+
+impl ConfigError {
+    fn from_deserialize(err: toml::de::Error) -> Self {
+        if BAD_COLOR.replace(false) {
+            ConfigError::BadColor(err)
+        } else if err.message().starts_with("unknown field") {
+            // serde's fixed wording for `deny_unknown_fields`
+            ConfigError::UnknownField(err)
+        } else {
+            ConfigError::InvalidValue(err)
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Syntax(err) => write!(f, "Invalid TOML: {err}"),
+            ConfigError::UnknownField(err) => write!(f, "Unknown option: {err}"),
+            ConfigError::BadColor(err) => write!(f, "Bad color: {err}"),
+            ConfigError::InvalidValue(err) => write!(f, "Invalid value: {err}"),
+            ConfigError::BadArgument(err) => write!(f, "Bad argument: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 impl Config {
     fn default_toml_overrides(config: &mut toml::Table) {
         // Hard-coded overrides for defaults.toml as:
         // - TOML lacks a None for option types
-        // - Users might copy the default.toml and we want the 'help'
-        //   CLI workaround to stay internal
+        // - Users might copy the default.toml and we want the 'help', 'preview'
+        //   and offline rendering CLI-only flags to stay internal
         config.remove("background_image");
+        config.remove("primary_output");
+        config.remove("status_socket_path");
+        config.remove("command_fifo");
+        config.remove("attempts_file");
+        config.remove("auth_user");
+        config.remove("external_auth_command");
+        config.remove("profile");
+        if let Some(toml::Value::Table(clock)) = config.get_mut("clock") {
+            clock.remove("timezone");
+        }
+        if let Some(toml::Value::Table(indicator)) = config.get_mut("indicator") {
+            indicator.remove("layout_font");
+            if let Some(toml::Value::Table(highlights)) = indicator.get_mut("highlights") {
+                highlights.remove("verifying");
+                highlights.remove("wrong");
+            }
+        }
+        if let Some(toml::Value::Table(avatar)) = config.get_mut("avatar") {
+            avatar.remove("path");
+        }
         config.insert("help".to_string(), toml::Value::Boolean(false));
+        config.insert("preview".to_string(), toml::Value::Boolean(false));
+        config.insert("width".to_string(), toml::Value::Integer(1920));
+        config.insert("height".to_string(), toml::Value::Integer(1080));
+        config.insert(
+            "render_state".to_string(),
+            toml::Value::String("typing".to_string()),
+        );
     }
 
     pub fn merge_config_with_defaults(user_config: toml::Table) -> toml::Table {
         let mut default_config = DEFAULT_CONFIG_STR.parse::<toml::Table>().unwrap();
+        Self::default_toml_overrides(&mut default_config);
+        merge_table(&default_config, &user_config)
+    }
 
-        fn merge_table(orig: &toml::Table, provided: &toml::Table) -> toml::Table {
-            let mut result = toml::Table::new();
-            for key in orig.keys() {
-                if let Some(toml::Value::Table(orig_table)) = orig.get(key)
-                    && let Some(toml::Value::Table(provided_table)) = provided.get(key)
-                {
-                    let new_table = merge_table(orig_table, provided_table);
-                    result.insert(key.clone(), toml::Value::Table(new_table));
-                } else if let Some(provided_value) = provided.get(key) {
-                    result.insert(key.clone(), provided_value.clone());
-                } else {
-                    result.insert(key.clone(), orig[key].clone());
-                }
-            }
-            for key in provided.keys() {
-                if !result.contains_key(key) {
-                    result.insert(key.clone(), provided[key].clone());
-                }
+    /// Takes the `[profiles]` tables out of the config and merges the one
+    /// named `profile` over the rest
+    fn apply_profile(
+        mut config: toml::Table,
+        profile: Option<&str>,
+    ) -> Result<toml::Table, String> {
+        let profiles = match config.remove("profiles") {
+            Some(toml::Value::Table(profiles)) => profiles,
+            Some(_) => return Err("'profiles' must be a table of profile tables".to_string()),
+            None => toml::Table::new(),
+        };
+        let Some(name) = profile else {
+            return Ok(config);
+        };
+        match profiles.get(name) {
+            Some(toml::Value::Table(profile)) => Ok(merge_table(&config, profile)),
+            Some(_) => Err(format!("Profile '{name}' must be a table")),
+            None => {
+                let known = profiles.keys().cloned().collect::<Vec<_>>();
+                Err(format!(
+                    "Unknown profile '{name}', the config defines: [{}]",
+                    known.join(", ")
+                ))
             }
-            result
         }
+    }
 
-        Self::default_toml_overrides(&mut default_config);
-        merge_table(&default_config, &user_config)
+    /// Merges the profile picked with `profile` over the config, then the CLI
+    /// arguments over both. The arguments are read twice as `--profile` is
+    /// one of them
+    fn merge_with_profile_and_parser(
+        config: toml::Table,
+        parser: impl Fn() -> lexopt::Parser,
+    ) -> Result<toml::Table, lexopt::Error> {
+        let with_args = Self::merge_with_parser(config.clone(), parser())?;
+        let profile = with_args.get("profile").and_then(toml::Value::as_str);
+        let config = Self::apply_profile(config, profile)?;
+        Self::merge_with_parser(config, parser())
     }
 
-    pub fn merge_with_args(mut config: toml::Table) -> Result<toml::Table, lexopt::Error> {
-        let parser = lexopt::Parser::from_env();
+    fn merge_with_parser(
+        mut config: toml::Table,
+        parser: lexopt::Parser,
+    ) -> Result<toml::Table, lexopt::Error> {
         let args_iter = ConfigArgsIter { parser };
 
         for arg in args_iter {
@@ -294,7 +857,12 @@ impl Config {
                 Some(toml::Value::String(_)) | None => {
                     toml::Value::String(value.parse::<String>()?)
                 }
-                Some(toml::Value::Integer(_)) => toml::Value::Integer(value.parse_with(parse_int)?),
+                Some(toml::Value::Integer(_)) => match value.parse_with(parse_int) {
+                    Ok(int) => toml::Value::Integer(int),
+                    // Colors set as integers in the config file can still be
+                    // overridden with the usual hex strings, `Color` handles both
+                    Err(_) => toml::Value::String(value.parse::<String>()?),
+                },
                 Some(toml::Value::Float(_)) => toml::Value::Float(value.parse::<f64>()?),
                 Some(toml::Value::Boolean(_)) => toml::Value::Boolean(value.parse::<bool>()?),
                 _ => {
@@ -309,12 +877,30 @@ impl Config {
         Ok(config)
     }
 
-    pub fn parse(config_str: &str) -> Self {
-        let user_config = config_str.parse::<toml::Table>().unwrap();
+    /// Parses `config_str` over the defaults, then the process arguments
+    /// over both
+    pub fn parse(config_str: &str) -> Result<Self, ConfigError> {
+        Self::parse_with_parser(config_str, lexopt::Parser::from_env)
+    }
+
+    fn parse_with_parser(
+        config_str: &str,
+        parser: impl Fn() -> lexopt::Parser,
+    ) -> Result<Self, ConfigError> {
+        let user_config = config_str
+            .parse::<toml::Table>()
+            .map_err(ConfigError::Syntax)?;
         let merged_config = Self::merge_config_with_defaults(user_config);
-        let merged_with_args = Self::merge_with_args(merged_config).unwrap();
-        let config: Self = Config::deserialize(merged_with_args).unwrap();
-        config
+        let merged_with_args = Self::merge_with_profile_and_parser(merged_config, parser)
+            .map_err(ConfigError::BadArgument)?;
+        BAD_COLOR.set(false);
+        Config::deserialize(merged_with_args).map_err(ConfigError::from_deserialize)
+    }
+
+    /// The default config, ignoring both the config file and the arguments
+    pub fn defaults() -> Self {
+        Config::deserialize(Self::merge_config_with_defaults(toml::Table::new()))
+            .expect("The default config is valid")
     }
 
     pub fn exclusive_config(config: Config) -> toml::Table {
@@ -347,6 +933,7 @@ impl Config {
                         (Value::Float(d), Value::Float(u)) => d == u,
                         (Value::Boolean(d), Value::Boolean(u)) => d == u,
                         (Value::Datetime(d), Value::Datetime(u)) => d == u,
+                        (Value::Array(d), Value::Array(u)) => d == u,
                         (_, _) => false,
                     };
                     if is_equal {
@@ -360,3 +947,192 @@ impl Config {
         config
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Like `Config::parse` without reading the process arguments
+    fn config_from(user_config: &str) -> Config {
+        let user_config = user_config.parse::<toml::Table>().unwrap();
+        Config::deserialize(Config::merge_config_with_defaults(user_config)).unwrap()
+    }
+
+    fn merged_with_args(user_config: &str, args: &[&str]) -> Result<toml::Table, lexopt::Error> {
+        let user_config = user_config.parse::<toml::Table>().unwrap();
+        let merged = Config::merge_config_with_defaults(user_config);
+        Config::merge_with_profile_and_parser(merged, || lexopt::Parser::from_args(args))
+    }
+
+    fn config_with_args(user_config: &str, args: &[&str]) -> Config {
+        Config::deserialize(merged_with_args(user_config, args).unwrap()).unwrap()
+    }
+
+    fn serialized(config: &Config) -> String {
+        toml::to_string(config).unwrap()
+    }
+
+    #[test]
+    fn default_config_is_elided() {
+        let exclusive = Config::exclusive_config(config_from(""));
+        assert!(exclusive.is_empty(), "{exclusive:#?}");
+    }
+
+    #[test]
+    fn colors_round_trip_through_exclusive_config() {
+        let config = config_from(
+            r#"
+            background_color = "102030FF"
+            [clock]
+            text_color = 0xAABBCC80
+            [indicator.colors.ring]
+            wrong = "01020304"
+            "#,
+        );
+
+        let exclusive = Config::exclusive_config(config.clone());
+        assert_eq!(exclusive["background_color"].as_str(), Some("102030FF"));
+        assert_eq!(exclusive["clock"]["text_color"].as_str(), Some("AABBCC80"));
+        assert_eq!(
+            exclusive["indicator"]["colors"]["ring"]["wrong"].as_str(),
+            Some("01020304")
+        );
+        assert_eq!(exclusive.len(), 3, "{exclusive:#?}");
+
+        let round_tripped = config_from(&exclusive.to_string());
+        assert_eq!(serialized(&round_tripped), serialized(&config));
+    }
+
+    #[test]
+    fn default_colors_are_elided() {
+        let exclusive = Config::exclusive_config(config_from(
+            r#"
+            background_color = "1D1D1DFF"
+            [clock]
+            outline_color = "0x1A1A1AC0"
+            "#,
+        ));
+        assert!(exclusive.is_empty(), "{exclusive:#?}");
+    }
+
+    #[test]
+    fn six_digit_colors_are_normalized_to_opaque() {
+        let exclusive = Config::exclusive_config(config_from(
+            r#"
+            background_color = "102030"
+            [clock]
+            text_color = "ffffff"
+            "#,
+        ));
+        assert_eq!(exclusive["background_color"].as_str(), Some("102030FF"));
+        // Same as the default "FFFFFFFF" once normalized
+        assert!(!exclusive.contains_key("clock"), "{exclusive:#?}");
+    }
+
+    #[test]
+    fn cli_colors_parse_as_colors() {
+        let config = config_with_args(
+            "",
+            &[
+                "--indicator.colors.ring.input=0xff0000ff",
+                "--background-color",
+                "00ff00",
+                "--clock.text-color",
+                "0x0000FF80",
+            ],
+        );
+
+        let exclusive = Config::exclusive_config(config);
+        assert_eq!(
+            exclusive["indicator"]["colors"]["ring"]["input"].as_str(),
+            Some("FF0000FF")
+        );
+        assert_eq!(exclusive["background_color"].as_str(), Some("00FF00FF"));
+        assert_eq!(exclusive["clock"]["text_color"].as_str(), Some("0000FF80"));
+    }
+
+    #[test]
+    fn cli_colors_override_integer_colors_from_the_config_file() {
+        let config = config_with_args(
+            "background_color = 0x102030FF",
+            &["--background-color", "ff0000ff"],
+        );
+
+        let exclusive = Config::exclusive_config(config);
+        assert_eq!(exclusive["background_color"].as_str(), Some("FF0000FF"));
+    }
+
+    const PROFILES: &str = r#"
+        dim = 0.5
+        [clock]
+        font_size = 20.0
+        [profiles.work.clock]
+        font_size = 40.0
+        font = "serif"
+        [profiles.home]
+        dim = 0.0
+        "#;
+
+    #[test]
+    fn profile_merges_over_the_config_and_under_cli_flags() {
+        let config = config_with_args(
+            PROFILES,
+            &["--profile", "work", "--clock.font", "monospace"],
+        );
+        assert_eq!(config.dim, 0.5);
+        assert_eq!(config.clock.font_size, 40.0);
+        assert_eq!(config.clock.font, "monospace");
+    }
+
+    #[test]
+    fn profile_can_be_picked_in_the_config() {
+        let config = config_with_args(&format!("profile = \"home\"\n{PROFILES}"), &[]);
+        assert_eq!((config.dim, config.clock.font_size), (0.0, 20.0));
+    }
+
+    #[test]
+    fn profiles_are_ignored_without_a_profile() {
+        let config = config_with_args(PROFILES, &[]);
+        assert_eq!((config.dim, config.clock.font_size), (0.5, 20.0));
+    }
+
+    #[test]
+    fn clock_position_takes_keywords_and_offsets() {
+        let config = config_from("[clock]\nx = \"left\"\ny = -120");
+        assert_eq!(config.clock.x, ClockPosition::Start);
+        assert_eq!(config.clock.y, ClockPosition::Offset(-120.0));
+        let config = config_with_args("", &["--clock.x=40.5", "--clock.y=bottom"]);
+        assert_eq!(config.clock.x, ClockPosition::Offset(40.5));
+        assert_eq!(config.clock.y, ClockPosition::End);
+    }
+
+    #[test]
+    fn config_errors_are_classified() {
+        let parse = |config: &str| {
+            Config::parse_with_parser(config, || lexopt::Parser::from_args(&[] as &[&str]))
+        };
+        assert!(matches!(parse("dim = "), Err(ConfigError::Syntax(_))));
+        assert!(matches!(
+            parse("dimm = 0.5"),
+            Err(ConfigError::UnknownField(_))
+        ));
+        assert!(matches!(
+            parse("background_color = \"red\""),
+            Err(ConfigError::BadColor(_))
+        ));
+        assert!(matches!(
+            parse("dim = \"half\""),
+            Err(ConfigError::InvalidValue(_))
+        ));
+        assert!(parse("dim = 0.25").is_ok());
+    }
+
+    #[test]
+    fn unknown_profile_is_an_error() {
+        let err = merged_with_args(PROFILES, &["--profile=gym"]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unknown profile 'gym', the config defines: [home, work]"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+//! Floyd–Steinberg dithering of rendered buffers to a few bits per channel,
+//! for e-ink and other panels with a reduced palette.
+
+/// Dithers the color channels of a native-endian ARGB32 buffer of `width`
+/// pixels per row to `bits` per channel. Alpha is kept, the colors being
+/// clamped to it so they stay valid premultiplied values
+pub fn dither(canvas: &mut [u8], width: usize, height: usize, bits: u32) {
+    if !(1..8).contains(&bits) || width == 0 {
+        return;
+    }
+    let max_level = ((1 << bits) - 1) as f32;
+    let quantize =
+        |value: f32| (value.clamp(0.0, 255.0) * max_level / 255.0).round() * 255.0 / max_level;
+
+    // Errors carried to the current and next rows, 3 channels per pixel with
+    // a pixel of margin on each side
+    let mut current = vec![0.0f32; (width + 2) * 3];
+    let mut next = vec![0.0f32; (width + 2) * 3];
+    for row in canvas.chunks_exact_mut(width * 4).take(height) {
+        for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+            let argb = u32::from_ne_bytes(pixel.try_into().unwrap());
+            let alpha = (argb >> 24) as f32;
+            let mut out = argb & 0xFF00_0000;
+            for channel in 0..3 {
+                let shift = 16 - channel * 8;
+                let i = (x + 1) * 3 + channel;
+                let value = ((argb >> shift) & 0xFF) as f32 + current[i];
+                let quantized = quantize(value).min(alpha);
+                let error = value - quantized;
+                current[i + 3] += error * 7.0 / 16.0;
+                next[i - 3] += error * 3.0 / 16.0;
+                next[i] += error * 5.0 / 16.0;
+                next[i + 3] += error / 16.0;
+                out |= (quantized as u32) << shift;
+            }
+            pixel.copy_from_slice(&out.to_ne_bytes());
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn one_bit_gray_averages_to_the_gray() {
+        let (width, height) = (16, 16);
+        let gray: u32 = 0xFF80_8080;
+        let mut canvas = gray.to_ne_bytes().repeat(width * height);
+        dither(&mut canvas, width, height, 1);
+
+        let pixels = canvas
+            .chunks_exact(4)
+            .map(|p| u32::from_ne_bytes(p.try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert!(
+            pixels
+                .iter()
+                .all(|p| *p == 0xFF00_0000 || *p == 0xFFFF_FFFF)
+        );
+        let white = pixels.iter().filter(|p| **p == 0xFFFF_FFFF).count();
+        assert!((white as i32 - (width * height / 2) as i32).abs() <= 8);
+    }
+}
@@ -1,5 +1,6 @@
 //! A double-buffered surface that attempts to be easy to use
 
+use log::error;
 use smithay_client_toolkit::{
     globals::ProvidesBoundGlobal,
     shm::slot::{Buffer, Slot, SlotPool},
@@ -21,11 +22,18 @@ struct EasySurfaceInner {
     slot_2: EasySlotBuffer,
     width: i32,
     height: i32,
+    /// Buffer pixels per surface coordinate
+    scale: i32,
 }
 
 pub struct EasySurface {
     surface: WlSurface,
     format: wl_shm::Format,
+    /// Buffers, allocated on the first `configure`. Nothing is attached
+    /// before: committing a buffer to a lock or layer surface before its
+    /// first configure is a protocol error. No placeholder is needed either,
+    /// the compositor hides the session itself and only sends `locked` once
+    /// no unlocked content can be visible on any output
     inner: Option<EasySurfaceInner>,
 }
 
@@ -60,27 +68,41 @@ impl EasySurface {
         }
     }
 
+    /// Sizes the surface to `width`x`height` surface coordinates, backed by
+    /// buffers `scale` times larger for HiDPI outputs
     pub fn configure(
         &mut self,
         shm: &impl ProvidesBoundGlobal<wl_shm::WlShm, 1>,
         width: i32,
         height: i32,
+        scale: i32,
     ) {
-        let old_size = self.get_size();
-        if let Some((old_width, old_height)) = old_size
-            && old_width == width
-            && old_height == height
+        if width <= 0 || height <= 0 {
+            // A pool can't be empty, stay unconfigured until a usable size
+            error!("Ignoring configure to an empty size {width}x{height}");
+            self.inner = None;
+            return;
+        }
+
+        let scale = scale.max(1);
+        if let Some(inner) = self.inner.as_ref()
+            && inner.width == width
+            && inner.height == height
+            && inner.scale == scale
         {
             return;
         }
 
-        let stride = width * 4;
-        let size = (stride as usize) * (height as usize);
+        // First or new size, e.g. a mode change while locked: fresh buffers
+        // report `resized` so everything is redrawn at the new size
+        let (buffer_width, buffer_height) = (width * scale, height * scale);
+        let stride = buffer_width * 4;
+        let size = (stride as usize) * (buffer_height as usize);
         let mut pool = SlotPool::new(size, shm).expect("Failed to create pool");
         let create = |pool: &mut SlotPool| {
             let slot = pool.new_slot(size).expect("Failed to create slot");
             let buffer = pool
-                .create_buffer_in(&slot, width, height, stride, self.format)
+                .create_buffer_in(&slot, buffer_width, buffer_height, stride, self.format)
                 .expect("Failed to create Buffer");
             return EasySlotBuffer {
                 slot,
@@ -89,23 +111,36 @@ impl EasySurface {
             };
         };
         let slots = (create(&mut pool), create(&mut pool));
+        self.surface.set_buffer_scale(scale);
         self.inner = Some(EasySurfaceInner {
             pool,
             slot_1: slots.0,
             slot_2: slots.1,
             width,
             height,
+            scale,
         });
     }
 
+    /// Makes the next renders of both buffers report `resized`, so content
+    /// that is only drawn on resize gets redrawn
+    pub fn invalidate(&mut self) {
+        if let Some(inner) = self.inner.as_mut() {
+            inner.slot_1.resized = true;
+            inner.slot_2.resized = true;
+        }
+    }
+
     #[allow(dead_code)]
     pub fn wl_surface(&self) -> &WlSurface {
         &self.surface
     }
 
+    /// Calls `render` with a free buffer, its canvas, the surface size, its
+    /// scale and whether the buffer is new, then commits it
     pub fn render<F, D>(&mut self, qh: &QueueHandle<D>, request_frame: bool, render: F) -> bool
     where
-        F: FnOnce(&mut Buffer, &mut [u8], i32, i32, bool) -> (),
+        F: FnOnce(&mut Buffer, &mut [u8], i32, i32, i32, bool),
         D: wayland_client::Dispatch<wl_callback::WlCallback, WlSurface> + 'static,
     {
         let mut inner = match self.inner.take() {
@@ -116,15 +151,16 @@ impl EasySurface {
             }
         };
 
-        let (width, height) = (inner.width, inner.height);
+        let (width, height, scale) = (inner.width, inner.height, inner.scale);
 
         // Render and commit if buffers are available, otherwise do nothing as the
         // other invoker would trigger a next frame
         let rendered = if let Some((slot_buffer, canvas)) = inner.get_active() {
             let buffer = &mut slot_buffer.buffer;
-            render(buffer, canvas, width, height, slot_buffer.resized);
+            render(buffer, canvas, width, height, scale, slot_buffer.resized);
             buffer.attach_to(&self.surface).unwrap();
-            self.surface.damage_buffer(0, 0, width, height);
+            self.surface
+                .damage_buffer(0, 0, width * scale, height * scale);
             self.surface.commit();
             if request_frame {
                 self.surface.frame(qh, self.surface.clone());
@@ -138,3 +174,122 @@ impl EasySurface {
         rendered
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::net::UnixStream;
+
+    use smithay_client_toolkit::error::GlobalError;
+    use wayland_client::{Connection, Dispatch, Proxy};
+
+    use super::*;
+
+    struct TestState;
+
+    impl Dispatch<wl_callback::WlCallback, WlSurface> for TestState {
+        fn event(
+            _: &mut Self,
+            _: &wl_callback::WlCallback,
+            _: wl_callback::Event,
+            _: &WlSurface,
+            _: &Connection,
+            _: &QueueHandle<Self>,
+        ) {
+        }
+    }
+
+    struct NoShm;
+
+    impl ProvidesBoundGlobal<wl_shm::WlShm, 1> for NoShm {
+        fn bound_global(&self) -> Result<wl_shm::WlShm, GlobalError> {
+            Err(GlobalError::MissingGlobal("wl_shm"))
+        }
+    }
+
+    /// A shm global that was never bound, whose pools and buffers are inert
+    struct InertShm(Connection);
+
+    impl ProvidesBoundGlobal<wl_shm::WlShm, 1> for InertShm {
+        fn bound_global(&self) -> Result<wl_shm::WlShm, GlobalError> {
+            Ok(wl_shm::WlShm::inert(self.0.backend().downgrade()))
+        }
+    }
+
+    /// A surface on a connection whose compositor end is `server`
+    fn test_surface() -> (Connection, UnixStream, EasySurface) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let conn = Connection::from_socket(client).unwrap();
+        let surface = WlSurface::inert(conn.backend().downgrade());
+        let surface = EasySurface::new(surface, wl_shm::Format::Argb8888);
+        (conn, server, surface)
+    }
+
+    fn assert_nothing_sent(conn: &Connection, server: &mut UnixStream) {
+        conn.flush().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let mut buffer = [0; 64];
+        let read = server.read(&mut buffer);
+        assert_eq!(
+            read.map_err(|err| err.kind()).unwrap_err(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn render_before_configure_is_a_noop() {
+        let (conn, mut server, mut surface) = test_surface();
+        let queue = conn.new_event_queue::<TestState>();
+
+        let mut called = false;
+        let rendered = surface.render(&queue.handle(), true, |_, _, _, _, _, _| called = true);
+
+        assert!(!rendered);
+        assert!(!called);
+        assert_nothing_sent(&conn, &mut server);
+    }
+
+    #[test]
+    fn configure_to_empty_size_stays_unconfigured() {
+        let (conn, mut server, mut surface) = test_surface();
+
+        surface.configure(&NoShm, 0, 1080, 1);
+
+        assert_eq!(surface.get_size(), None);
+        assert_nothing_sent(&conn, &mut server);
+    }
+
+    #[test]
+    fn reconfigure_to_new_size_recreates_resized_buffers() {
+        let (conn, _server, mut surface) = test_surface();
+        let shm = InertShm(conn.clone());
+        // Full rows of the buffer drawn next, slots being padded, and whether
+        // it would be drawn as resized
+        let next_render = |surface: &mut EasySurface, width: usize| {
+            let inner = surface.inner.as_mut().unwrap();
+            let (slot_buffer, canvas) = inner.get_active().unwrap();
+            (canvas.len() / (width * 4), slot_buffer.resized)
+        };
+
+        surface.configure(&shm, 100, 50, 1);
+        assert_eq!(next_render(&mut surface, 100), (50, true));
+
+        // As after both buffers were drawn, then a configure of the same size
+        // which must keep them
+        let inner = surface.inner.as_mut().unwrap();
+        inner.slot_1.resized = false;
+        inner.slot_2.resized = false;
+        surface.configure(&shm, 100, 50, 1);
+        assert_eq!(next_render(&mut surface, 100), (50, false));
+
+        // A live mode change: buffers of the new size that get drawn in full
+        surface.configure(&shm, 200, 150, 1);
+        assert_eq!(surface.get_size(), Some((200, 150)));
+        assert_eq!(next_render(&mut surface, 200), (150, true));
+
+        // Moved to a HiDPI output: same surface size, buffers twice as large
+        surface.configure(&shm, 200, 150, 2);
+        assert_eq!(surface.get_size(), Some((200, 150)));
+        assert_eq!(next_render(&mut surface, 400), (300, true));
+    }
+}
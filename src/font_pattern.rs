@@ -0,0 +1,100 @@
+//! Fonts given as fontconfig patterns, like
+//! `"JetBrains Mono:weight=bold:slant=italic"`, which cairo's family-only
+//! `select_font_face` can't express. The pattern is parsed and resolved by
+//! fontconfig to the best matching installed font, which FreeType loads, so
+//! the whole fontconfig pattern syntax is supported.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::{CStr, CString, c_char, c_int};
+use std::ptr;
+
+use cairo::freetype::Library;
+use fontconfig_sys::constants::{FC_FILE, FC_INDEX};
+use fontconfig_sys::{
+    FcChar8, FcConfigSubstitute, FcDefaultSubstitute, FcFontMatch, FcMatchPattern, FcNameParse,
+    FcPattern, FcPatternDestroy, FcPatternGetInteger, FcPatternGetString, FcResultMatch,
+};
+use log::error;
+
+thread_local! {
+    /// Faces by pattern, parsing them once rather than on every frame
+    static FACES: RefCell<HashMap<String, Option<cairo::FontFace>>> = RefCell::new(HashMap::new());
+}
+
+/// Whether `font` uses the pattern syntax rather than being a family name,
+/// which may contain dashes like "sans-serif" but not colons
+pub fn is_pattern(font: &str) -> bool {
+    font.contains(':')
+}
+
+/// Face of the fontconfig `pattern`, or None if it doesn't parse or match
+pub fn font_face(pattern: &str) -> Option<cairo::FontFace> {
+    FACES.with_borrow_mut(|faces| {
+        faces
+            .entry(pattern.to_string())
+            .or_insert_with(|| {
+                let face = create_font_face(pattern);
+                if face.is_none() {
+                    error!("Invalid font pattern '{pattern}', using it as a family name");
+                }
+                face
+            })
+            .clone()
+    })
+}
+
+/// Fontconfig pattern, destroyed when dropped
+struct OwnedPattern(*mut FcPattern);
+
+impl Drop for OwnedPattern {
+    fn drop(&mut self) {
+        // SAFETY: the pattern is owned and not used after this
+        unsafe { FcPatternDestroy(self.0) };
+    }
+}
+
+/// File and face index of the installed font best matching `pattern`
+fn match_font_file(pattern: &str) -> Option<(String, c_int)> {
+    let pattern = CString::new(pattern).ok()?;
+    // SAFETY: the patterns are checked for null before use and only freed
+    // once by `OwnedPattern`, the file name is copied out before that
+    unsafe {
+        let parsed = FcNameParse(pattern.as_ptr() as *const FcChar8);
+        if parsed.is_null() {
+            return None;
+        }
+        let parsed = OwnedPattern(parsed);
+        // Fills in what the pattern leaves out as fc-match does, as cairo
+        // doesn't match the family of an unresolved pattern
+        FcConfigSubstitute(ptr::null_mut(), parsed.0, FcMatchPattern);
+        FcDefaultSubstitute(parsed.0);
+        let mut result = 0;
+        let matched = FcFontMatch(ptr::null_mut(), parsed.0, &mut result);
+        if matched.is_null() {
+            return None;
+        }
+        let matched = OwnedPattern(matched);
+        let mut file = ptr::null_mut();
+        let found = FcPatternGetString(matched.0, FC_FILE.as_ptr(), 0, &mut file);
+        if found != FcResultMatch || file.is_null() {
+            return None;
+        }
+        let file = CStr::from_ptr(file as *const c_char)
+            .to_str()
+            .ok()?
+            .to_string();
+        let mut index = 0;
+        if FcPatternGetInteger(matched.0, FC_INDEX.as_ptr(), 0, &mut index) != FcResultMatch {
+            index = 0;
+        }
+        Some((file, index))
+    }
+}
+
+fn create_font_face(pattern: &str) -> Option<cairo::FontFace> {
+    let (path, index) = match_font_file(pattern)?;
+    // The face keeps its library alive
+    let face = Library::init().ok()?.new_face(path, index as isize).ok()?;
+    cairo::FontFace::create_from_ft(&face).ok()
+}
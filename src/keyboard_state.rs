@@ -1,48 +1,197 @@
 use std::collections::HashMap;
 
+use log::error;
 use smithay_client_toolkit::seat::keyboard;
-use wayland_client::protocol::wl_keyboard;
+use wayland_client::{Proxy, protocol::wl_keyboard};
+use xkbcommon::xkb;
+
+use crate::config::Modifier;
 
 pub struct KeyboardState {
-    _keyboard: Option<wl_keyboard::WlKeyboard>,
+    keyboard: Option<wl_keyboard::WlKeyboard>,
+    /// Mirrors the compositor's modifier state so lock/level/group state can
+    /// be queried directly. `None` until a keymap has been parsed.
+    xkb_state: Option<xkb::State>,
     layouts: HashMap<u32, String>,
     active_layout: u32,
-    pub is_caps_lock: bool,
+    modifiers: keyboard::Modifiers,
     pub is_control: bool,
 }
 
 impl KeyboardState {
     pub fn new(keyboard: Option<wl_keyboard::WlKeyboard>) -> Self {
         Self {
-            _keyboard: keyboard,
+            keyboard,
+            xkb_state: None,
             layouts: HashMap::new(),
             active_layout: 0,
-            is_caps_lock: false,
+            modifiers: keyboard::Modifiers::default(),
             is_control: false,
         }
     }
 
+    /// Whether the seat currently provides a keyboard to type the password with
+    pub fn is_present(&self) -> bool {
+        self.keyboard.is_some()
+    }
+
+    /// Releases the keyboard after the seat lost its keyboard capability
+    pub fn release(&mut self) {
+        if let Some(keyboard) = self.keyboard.take()
+            && keyboard.version() >= 3
+        {
+            keyboard.release();
+        }
+    }
+
     pub fn parse_keymap_layouts(&mut self, keymap: keyboard::Keymap<'_>) {
-        use xkbcommon::xkb;
+        self.parse_keymap_string(keymap.as_string());
+    }
+
+    /// Replaces the known layouts with those of `keymap`. An unparsable keymap
+    /// keeps the previous layouts as panicking here would leave the session
+    /// locked with no way to unlock it.
+    fn parse_keymap_string(&mut self, keymap: String) {
         let ctx = xkb::Context::new(0);
-        let keymap =
-            xkb::Keymap::new_from_string(&ctx, keymap.as_string(), xkb::KEYMAP_FORMAT_TEXT_V1, 0)
-                .unwrap();
+        let Some(keymap) =
+            xkb::Keymap::new_from_string(&ctx, keymap, xkb::KEYMAP_FORMAT_TEXT_V1, 0)
+        else {
+            error!("Failed to parse keymap, keeping previous keyboard layouts");
+            return;
+        };
         self.layouts = HashMap::new();
         for (idx, layout) in keymap.layouts().enumerate() {
             self.layouts.insert(idx as u32, layout.to_string());
         }
+        self.xkb_state = Some(xkb::State::new(&keymap));
+        self.sync_xkb_state();
     }
 
-    pub fn set_active_layout(&mut self, layout: u32) {
+    pub fn update_modifiers(&mut self, modifiers: keyboard::Modifiers, layout: u32) {
+        self.modifiers = modifiers;
+        self.is_control = modifiers.ctrl;
         self.active_layout = layout;
+        self.sync_xkb_state();
+    }
+
+    fn sync_xkb_state(&mut self) {
+        let Some(state) = self.xkb_state.as_mut() else {
+            return;
+        };
+        let keymap = state.get_keymap();
+        let mask = |mods: &[(bool, &str)]| {
+            mods.iter()
+                .filter(|(active, _)| *active)
+                .map(|(_, name)| keymap.mod_get_index(*name))
+                .filter(|idx| *idx != xkb::MOD_INVALID)
+                .fold(0, |mask, idx| mask | (1 << idx))
+        };
+        let modifiers = &self.modifiers;
+        let depressed = mask(&[
+            (modifiers.ctrl, xkb::MOD_NAME_CTRL),
+            (modifiers.alt, xkb::MOD_NAME_ALT),
+            (modifiers.shift, xkb::MOD_NAME_SHIFT),
+            (modifiers.logo, xkb::MOD_NAME_LOGO),
+        ]);
+        let locked = mask(&[
+            (modifiers.caps_lock, xkb::MOD_NAME_CAPS),
+            (modifiers.num_lock, xkb::MOD_NAME_NUM),
+        ]);
+        state.update_mask(depressed, 0, locked, 0, 0, self.active_layout);
+    }
+
+    fn is_mod_active(&self, name: &str, fallback: bool) -> bool {
+        match self.xkb_state.as_ref() {
+            Some(state) => state.mod_name_is_active(name, xkb::STATE_MODS_EFFECTIVE),
+            None => fallback,
+        }
+    }
+
+    pub fn is_caps_lock(&self) -> bool {
+        self.is_mod_active(xkb::MOD_NAME_CAPS, self.modifiers.caps_lock)
+    }
+
+    pub fn is_num_lock(&self) -> bool {
+        self.is_mod_active(xkb::MOD_NAME_NUM, self.modifiers.num_lock)
+    }
+
+    pub fn is_modifier_active(&self, modifier: Modifier) -> bool {
+        match modifier {
+            Modifier::Shift => self.is_mod_active(xkb::MOD_NAME_SHIFT, self.modifiers.shift),
+            Modifier::Ctrl => self.is_mod_active(xkb::MOD_NAME_CTRL, self.modifiers.ctrl),
+            Modifier::Alt => self.is_mod_active(xkb::MOD_NAME_ALT, self.modifiers.alt),
+            Modifier::Super => self.is_mod_active(xkb::MOD_NAME_LOGO, self.modifiers.logo),
+            Modifier::CapsLock => self.is_caps_lock(),
+            Modifier::NumLock => self.is_num_lock(),
+        }
+    }
+
+    /// Whether the keymap wants `raw_code` to repeat while held
+    pub fn key_repeats(&self, raw_code: u32) -> bool {
+        self.xkb_state.as_ref().is_some_and(|state| {
+            state
+                .get_keymap()
+                .key_repeats(xkb::Keycode::new(raw_code + 8))
+        })
+    }
+
+    pub fn get_active_layout(&self) -> Option<&str> {
+        self.layouts.get(&self.active_layout).map(String::as_str)
     }
 
-    pub fn get_active_layout(&self) -> &str {
-        &self.layouts[&self.active_layout]
+    pub fn get_active_layout_index(&self) -> u32 {
+        self.active_layout
     }
 
     pub fn get_num_layouts(&self) -> usize {
         self.layouts.len()
     }
 }
+
+/// Character typed by `keysym`, for key events that come without text. Only
+/// printable characters are returned, never controls like Return or Tab, and
+/// dead keys or modifiers have none.
+pub fn keysym_char(keysym: keyboard::Keysym) -> Option<char> {
+    char::from_u32(xkb::keysym_to_utf32(keysym)).filter(|c| *c != '\0' && !c.is_control())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn printable_keysyms_have_a_char() {
+        use keyboard::Keysym;
+
+        assert_eq!(keysym_char(Keysym::a), Some('a'));
+        assert_eq!(keysym_char(Keysym::Z), Some('Z'));
+        assert_eq!(keysym_char(Keysym::eacute), Some('é'));
+        assert_eq!(keysym_char(Keysym::Cyrillic_be), Some('б'));
+        assert_eq!(keysym_char(Keysym::KP_5), Some('5'));
+        assert_eq!(keysym_char(Keysym::space), Some(' '));
+    }
+
+    #[test]
+    fn non_printable_keysyms_have_no_char() {
+        use keyboard::Keysym;
+
+        assert_eq!(keysym_char(Keysym::Return), None);
+        assert_eq!(keysym_char(Keysym::BackSpace), None);
+        assert_eq!(keysym_char(Keysym::Tab), None);
+        assert_eq!(keysym_char(Keysym::Escape), None);
+        assert_eq!(keysym_char(Keysym::Shift_L), None);
+        assert_eq!(keysym_char(Keysym::dead_acute), None);
+        assert_eq!(keysym_char(Keysym::NoSymbol), None);
+    }
+
+    #[test]
+    fn invalid_keymap_keeps_previous_layouts() {
+        let mut keyboard = KeyboardState::new(None);
+        keyboard.layouts.insert(0, "English (US)".to_string());
+
+        keyboard.parse_keymap_string("xkb_keymap { not a keymap".to_string());
+
+        assert_eq!(keyboard.get_num_layouts(), 1);
+        assert_eq!(keyboard.get_active_layout(), Some("English (US)"));
+    }
+}
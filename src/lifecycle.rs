@@ -0,0 +1,130 @@
+//! Stages of a lock, from waiting for the compositor to lock the session
+//! until the event loop ends, advanced once per event loop iteration
+
+use std::time::Instant;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum LifeCycle {
+    Initing,
+    Locked,
+    /// Authenticated, fading out the surfaces before releasing the lock
+    FadingOut(Instant),
+    Authenticated,
+    Ended,
+}
+
+/// What an event loop iteration observed that can advance the lifecycle
+#[derive(Copy, Clone, Debug)]
+pub struct Signals {
+    /// SIGUSR1 was received, unlocking without authentication
+    pub sigusr1: bool,
+    /// Unlocking fades the surfaces out first, `fade_out_ms` being set
+    pub fade_out: bool,
+    /// A running fade out has lasted `fade_out_ms`
+    pub fade_out_done: bool,
+    /// Time of the iteration, when a fade out would start
+    pub now: Instant,
+}
+
+/// Stage following `current`. `lock_present` is whether the compositor
+/// confirmed the lock, or a preview stands in for it
+pub fn next_lifecycle(current: LifeCycle, signals: Signals, lock_present: bool) -> LifeCycle {
+    match current {
+        LifeCycle::Initing if lock_present => LifeCycle::Locked,
+        LifeCycle::Initing => LifeCycle::Initing,
+        LifeCycle::Locked if signals.sigusr1 && signals.fade_out => {
+            LifeCycle::FadingOut(signals.now)
+        }
+        LifeCycle::Locked if signals.sigusr1 => LifeCycle::Authenticated,
+        LifeCycle::Locked => LifeCycle::Locked,
+        LifeCycle::FadingOut(_) if signals.fade_out_done => LifeCycle::Authenticated,
+        LifeCycle::FadingOut(start) => LifeCycle::FadingOut(start),
+        // One more iteration lets the unlock reach the compositor
+        LifeCycle::Authenticated => LifeCycle::Ended,
+        LifeCycle::Ended => LifeCycle::Ended,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signals() -> Signals {
+        Signals {
+            sigusr1: false,
+            fade_out: false,
+            fade_out_done: false,
+            now: Instant::now(),
+        }
+    }
+
+    #[test]
+    fn initing_waits_for_the_lock() {
+        assert_eq!(
+            next_lifecycle(LifeCycle::Initing, signals(), false),
+            LifeCycle::Initing
+        );
+        assert_eq!(
+            next_lifecycle(LifeCycle::Initing, signals(), true),
+            LifeCycle::Locked
+        );
+    }
+
+    #[test]
+    fn sigusr1_before_the_lock_is_kept_for_once_locked() {
+        let sigusr1 = Signals {
+            sigusr1: true,
+            ..signals()
+        };
+        // The flag stays set, so the next iteration after locking unlocks
+        let locked = next_lifecycle(LifeCycle::Initing, sigusr1, true);
+        assert_eq!(locked, LifeCycle::Locked);
+        assert_eq!(
+            next_lifecycle(locked, sigusr1, true),
+            LifeCycle::Authenticated
+        );
+    }
+
+    #[test]
+    fn locked_stays_locked_without_sigusr1() {
+        assert_eq!(
+            next_lifecycle(LifeCycle::Locked, signals(), true),
+            LifeCycle::Locked
+        );
+        // Losing the lock isn't a reason to unlock
+        assert_eq!(
+            next_lifecycle(LifeCycle::Locked, signals(), false),
+            LifeCycle::Locked
+        );
+    }
+
+    #[test]
+    fn sigusr1_unlocks_after_fading_out_if_enabled() {
+        let sigusr1 = Signals {
+            sigusr1: true,
+            fade_out: true,
+            ..signals()
+        };
+        let fading = next_lifecycle(LifeCycle::Locked, sigusr1, true);
+        assert_eq!(fading, LifeCycle::FadingOut(sigusr1.now));
+
+        assert_eq!(next_lifecycle(fading, sigusr1, true), fading);
+        let done = Signals {
+            fade_out_done: true,
+            ..sigusr1
+        };
+        assert_eq!(next_lifecycle(fading, done, true), LifeCycle::Authenticated);
+    }
+
+    #[test]
+    fn authenticated_ends_on_the_next_iteration() {
+        assert_eq!(
+            next_lifecycle(LifeCycle::Authenticated, signals(), false),
+            LifeCycle::Ended
+        );
+        assert_eq!(
+            next_lifecycle(LifeCycle::Ended, signals(), false),
+            LifeCycle::Ended
+        );
+    }
+}
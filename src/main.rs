@@ -1,14 +1,32 @@
+mod attempts_file;
+mod audit;
 mod auth;
+mod avatar;
 mod background_image;
+mod background_watch;
+mod blur;
 mod cairo_extras;
+mod command_fifo;
 mod config;
+mod dither;
 mod easy_surface;
+mod font_pattern;
 mod keyboard_state;
+mod lifecycle;
+mod output_debounce;
 mod overlay;
+#[cfg(feature = "preview")]
+mod preview;
+mod render_png;
+mod scene;
+mod screenshot;
+mod slideshow;
+mod status_socket;
 mod swaylock_config;
+mod theme_reload;
 
 use crate::{
-    auth::{PasswordBuffer, create_and_run_auth_loop},
+    auth::{AuthResult, PasswordBuffer, create_and_run_auth_loop},
     cairo_extras::CairoExtras,
     keyboard_state::KeyboardState,
 };
@@ -19,22 +37,32 @@ use std::{
     time::{Duration, Instant},
 };
 
-use log::error;
+use log::{debug, error, info, warn};
 
 use smithay_client_toolkit::{
     compositor::{CompositorHandler, CompositorState},
-    delegate_compositor, delegate_keyboard, delegate_output, delegate_registry, delegate_seat,
-    delegate_session_lock, delegate_shm, delegate_subcompositor,
+    delegate_compositor, delegate_keyboard, delegate_output, delegate_pointer, delegate_registry,
+    delegate_seat, delegate_session_lock, delegate_shm, delegate_subcompositor, delegate_touch,
     output::{OutputHandler, OutputState},
     reexports::{
-        calloop::{EventLoop, LoopHandle, LoopSignal, channel},
+        calloop::{
+            EventLoop, LoopHandle, LoopSignal, RegistrationToken, channel,
+            timer::{TimeoutAction, Timer},
+        },
         calloop_wayland_source::WaylandSource,
+        protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::{
+            Shape, WpCursorShapeDeviceV1,
+        },
     },
     registry::{ProvidesRegistryState, RegistryState},
     registry_handlers,
     seat::{
         self, SeatHandler, SeatState,
         keyboard::{self, KeyboardHandler},
+        pointer::{
+            PointerEvent, PointerEventKind, PointerHandler, cursor_shape::CursorShapeManager,
+        },
+        touch::TouchHandler,
     },
     session_lock::{
         SessionLock, SessionLockHandler, SessionLockState, SessionLockSurface,
@@ -47,25 +75,39 @@ use wayland_client::{
     Connection, Proxy, QueueHandle,
     backend::ObjectId,
     globals::registry_queue_init,
-    protocol::{wl_keyboard, wl_output, wl_seat, wl_shm, wl_surface},
+    protocol::{wl_keyboard, wl_output, wl_pointer, wl_seat, wl_shm, wl_surface, wl_touch},
 };
 
 use crate::{
-    background_image::{load_image, render_background_image},
-    config::Config,
+    audit::SecurityEvent,
+    avatar::load_avatar,
+    background_image::{load_configured_image, try_load_image},
+    config::{ActivitySource, BackgroundMode, Config, EscapeAction, ExternalAuthMode},
     easy_surface::EasySurface,
-    overlay::{Clock, Indicator},
+    lifecycle::{LifeCycle, Signals, next_lifecycle},
+    output_debounce::OutputDebounce,
+    overlay::{Avatar, Clock, Indicator, ModifierBadges, OverlayCard, PinKey},
+    scene::Scene,
+    screenshot::Screenshots,
+    slideshow::Slideshow,
+    status_socket::StatusSocket,
 };
 
-fn main() {
-    env_logger::init();
-
+/// Reads `waylockrs/config.toml` from the XDG config directory, else maps
+/// the swaylock config
+fn read_config_str() -> String {
     let xdg_dirs = xdg::BaseDirectories::new();
     let config_path = Path::new("waylockrs/config.toml");
-    let config_str = match xdg_dirs.get_config_file(config_path) {
+    match xdg_dirs.get_config_file(config_path) {
         Some(file) => {
             if file.exists() {
-                std::fs::read_to_string(file).unwrap()
+                std::fs::read_to_string(&file).unwrap_or_else(|err| {
+                    error!(
+                        "Unable to read {}: {err}. Using empty config.",
+                        file.display()
+                    );
+                    "".to_string()
+                })
             } else {
                 swaylock_config::try_mapping_swalock_config(&xdg_dirs, &config_path)
             }
@@ -74,22 +116,55 @@ fn main() {
             error!("Unable to retrieve XDG config directory. Using empty config.");
             "".to_string()
         }
-    };
+    }
+}
+
+/// Parses the config, falling back to the defaults when it or the arguments
+/// are invalid, as a typo must never keep the session from locking
+fn load_config() -> Config {
+    Config::parse(&read_config_str()).unwrap_or_else(|err| {
+        error!("Invalid config: {err}. Using the defaults.");
+        Config::parse("").unwrap_or_else(|err| {
+            error!("Ignoring the arguments: {err}");
+            Config::defaults()
+        })
+    })
+}
 
-    let config = Config::parse(&config_str);
+fn main() {
+    env_logger::init();
+
+    let mut config = load_config();
     if config.show_help {
         println!("Usage: waylockrs --background-image path/to/image");
         println!("Please refer to the default config for all options");
         println!("");
         println!("Note: config can be specified in $XDG_CONFIG_DIR/waylockrs/config.toml");
         println!("Note: or via CLI, e.g. --clock.font-size=100.0");
+        println!("Note: --profile <name> applies the [profiles.<name>] table of the config");
+        #[cfg(feature = "preview")]
+        println!("Note: --preview shows an insecure, non-locking preview for theming");
+        println!("Note: --render-to-png out.png [--width W --height H] [--render-state wrong]");
+        println!("Note: renders the lock screen offline instead of locking");
         return;
     }
 
-    if config.daemonize {
-        daemon(false, true).unwrap();
+    if let Some(path) = config.render_to_png.as_deref() {
+        if let Err(err) = render_png::render_to_png(&config, path) {
+            error!("Failed to render to {path}: {err}");
+        }
+        return;
     }
 
+    if config.preview && !cfg!(feature = "preview") {
+        error!("--preview requires waylockrs to be built with the 'preview' feature");
+        return;
+    }
+    if config.escape_action == EscapeAction::Quit && !config.preview {
+        warn!("escape_action 'quit' only closes --preview, Escape is ignored while locked");
+    }
+    auth::check_auth_user(&mut config);
+
     let conn = Connection::connect_to_env().unwrap();
 
     let (globals, event_queue) = registry_queue_init(&conn).unwrap();
@@ -108,14 +183,7 @@ fn main() {
         .insert(loop_handle)
         .expect("Failed to insert loop_handle");
 
-    let background_image = if config.background_mode != config::BackgroundMode::SolidColor {
-        match &config.background_image {
-            Some(path) => Some(load_image(&path)),
-            None => None,
-        }
-    } else {
-        None
-    };
+    let background_image = load_configured_image(&config);
 
     let mut state = State {
         loop_handle: event_loop.handle(),
@@ -126,85 +194,161 @@ fn main() {
         seat_state: SeatState::new(&globals, &qh),
         shm_state: Shm::bind(&globals, &qh).expect("wl_shm not available"),
         session_lock_state: SessionLockState::new(&globals, &qh),
+        cursor_shape_manager: CursorShapeManager::bind(&globals, &qh).ok(),
+        #[cfg(feature = "preview")]
+        layer_shell: preview::bind_layer_shell(&config, &globals, &qh),
 
         config: config.clone(),
         background_image,
+        slideshow: Slideshow::default(),
+        screenshots: Screenshots::default(),
         lock: None,
         lock_surfaces: HashMap::new(),
         output_to_lock_surfaces: HashMap::new(),
+        surface_outputs: HashMap::new(),
+        primary_surface: None,
+        clicked_surface: None,
+        focused_surface: None,
         keyboard: KeyboardState::new(None),
+        key_repeat: None,
+        pointer: None,
+        touch: None,
+        touch_keys: HashMap::new(),
+        cursor_shape_device: None,
         password: PasswordBuffer::new(),
         lifecycle: LifeCycle::Initing,
         end_signal: event_loop.get_signal(),
         auth_req_send: None,
-        indicator: Indicator {
-            config: config.indicator.clone(),
-            input_state: overlay::InputState::Idle,
-            auth_state: overlay::AuthState::Idle,
-            failed_attempts: overlay::AttemptsCounter::new(),
-            is_caps_lock: false,
-            last_update: Instant::now(),
-            highlight_start: 0,
+        indicator: Indicator::new(config.indicator.clone()),
+        clock: Clock::new(config.clock.clone()),
+        overlay_card: OverlayCard {
+            config: config.overlay_card.clone(),
+        },
+        modifier_badges: ModifierBadges {
+            config: config.modifiers.clone(),
         },
-        clock: Clock {
-            config: config.clock.clone(),
+        avatar: Avatar {
+            config: config.avatar.clone(),
+            image: config.show_avatar.then(|| load_avatar(&config)).flatten(),
         },
         sigusr_received: Arc::new(AtomicBool::new(false)),
+        reload_requested: Arc::new(AtomicBool::new(false)),
+        status_socket: StatusSocket::default(),
+        output_debounce: OutputDebounce::default(),
+        backoff_failures: 0,
+        verifying_since: None,
+        submit_when_locked: false,
+        frame_surface: None,
+        redraw_requested: false,
     };
 
+    state.indicator.is_initializing = true;
+    state.indicator.is_asleep = state.config.cold_start;
+    if let Some(path) = state.config.attempts_file.as_deref() {
+        let failed_attempts = attempts_file::load(Path::new(path));
+        state.indicator.failed_attempts.set(failed_attempts);
+        state.backoff_failures = failed_attempts;
+        // Restarting the locker during a lockout starts it over
+        state.lockout_if_due();
+    }
+
     // Early dispatch to fastly create lock surfaces
     event_loop.dispatch(None, &mut state).unwrap();
-    let lock = state.session_lock_state.lock(&qh).expect("Could not lock");
-    for output in state.output_state.outputs() {
-        state.create_lock_surface(&qh, &lock, output);
+    if state.config.background_mode == BackgroundMode::Screenshot {
+        // The compositor hides the session once locked
+        state.capture_screenshots(&globals, &qh);
+        let deadline = Instant::now() + screenshot::CAPTURE_TIMEOUT;
+        while state.screenshots.is_capturing() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                state.fail_pending_captures();
+                break;
+            }
+            event_loop.dispatch(remaining, &mut state).unwrap();
+        }
+    }
+    if state.config.preview {
+        #[cfg(feature = "preview")]
+        for output in state.output_state.outputs() {
+            state.create_preview_surface(&qh, output);
+        }
+    } else {
+        let lock = state.session_lock_state.lock(&qh).expect("Could not lock");
+        state.create_lock_surfaces(&qh, &lock);
+    }
+    // Nothing to draw yet: every surface is drawn from its first configure,
+    // and `draw` skips those without a size rather than committing early
+
+    if state.config.daemonize {
+        // Detach only once locked, so that failing to connect or to lock is
+        // still reported on the terminal, and before the auth thread exists
+        // as only the forking thread lives on in the daemon
+        while !state.config.preview && state.lock.is_none() {
+            event_loop.dispatch(None, &mut state).unwrap();
+        }
+        daemon(false, true).unwrap();
     }
-    state.draw(&conn, &qh);
 
     state.create_auth_channel(&mut event_loop);
     state.create_sigusr_interrupt_handler();
+    state.create_theme_reload_handler();
+    if state.config.watch_background {
+        state.create_background_watch();
+    }
+    state.create_slideshow();
+    state.create_status_socket();
+    state.create_command_fifo();
+    state.create_clock_tick(&conn, &qh);
 
     event_loop
         .run(None, &mut state, |state| {
-            state.lifecycle = match state.lifecycle {
-                LifeCycle::Initing => {
-                    if state.lock.is_some() {
-                        state.notify_ready_fd();
-                        LifeCycle::Locked
-                    } else {
-                        LifeCycle::Initing
-                    }
-                }
-                LifeCycle::Locked => {
-                    if state
-                        .sigusr_received
-                        .load(std::sync::atomic::Ordering::Relaxed)
-                    {
-                        if let Some(lock) = state.lock.take() {
-                            lock.unlock();
-                        }
-                        state.lock_surfaces.clear();
-                        LifeCycle::Authenticated
-                    } else {
-                        LifeCycle::Locked
+            state.reload_theme_if_requested();
+            let fade_out = Duration::from_millis(state.config.fade_out_ms);
+            let signals = Signals {
+                sigusr1: state
+                    .sigusr_received
+                    .load(std::sync::atomic::Ordering::Relaxed),
+                fade_out: !fade_out.is_zero(),
+                fade_out_done: matches!(
+                    state.lifecycle,
+                    LifeCycle::FadingOut(start) if start.elapsed() >= fade_out
+                ),
+                now: Instant::now(),
+            };
+            let lock_present = state.lock.is_some() || state.config.preview;
+            let next = next_lifecycle(state.lifecycle, signals, lock_present);
+            if next != state.lifecycle {
+                state.redraw_requested = true;
+            }
+            match (state.lifecycle, next) {
+                (LifeCycle::Initing, LifeCycle::Locked) => {
+                    state.notify_ready_fd();
+                    let shown = Duration::from_secs(state.config.indicator.show_on_lock_secs);
+                    state.indicator.visible_until = Some(Instant::now() + shown);
+                    state.indicator.is_initializing = false;
+                    state.lifecycle = next;
+                    if std::mem::take(&mut state.submit_when_locked) {
+                        state.submit_password();
                     }
                 }
-                LifeCycle::Authenticated => LifeCycle::Ended,
-                LifeCycle::Ended => {
-                    state.end_signal.stop();
-                    LifeCycle::Ended
+                // Moves to the same stage, after recording the unlock
+                (LifeCycle::Locked, LifeCycle::FadingOut(_) | LifeCycle::Authenticated) => {
+                    state.unlock("SIGUSR1")
                 }
-            };
+                (LifeCycle::FadingOut(_), LifeCycle::Authenticated) => state.finish_unlock(),
+                (LifeCycle::Ended, _) => state.end_signal.stop(),
+                _ => state.lifecycle = next,
+            }
+            state.publish_status();
+            // Frames are only requested while animating, so anything else
+            // that changes the screen has to start a redraw itself
+            if std::mem::take(&mut state.redraw_requested) && state.frame_surface.is_none() {
+                state.draw(&conn, &qh);
+            }
         })
         .unwrap();
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
-enum LifeCycle {
-    Initing,
-    Locked,
-    Authenticated,
-    Ended,
-}
 struct State {
     loop_handle: LoopHandle<'static, Self>,
     registry_state: RegistryState,
@@ -214,12 +358,34 @@ struct State {
     shm_state: Shm,
     seat_state: SeatState,
     session_lock_state: SessionLockState,
+    cursor_shape_manager: Option<CursorShapeManager>,
+    #[cfg(feature = "preview")]
+    layer_shell: Option<smithay_client_toolkit::shell::wlr_layer::LayerShell>,
 
     config: Config,
     background_image: Option<cairo::ImageSurface>,
+    slideshow: Slideshow,
+    /// Taken before locking with `background_mode = "screenshot"`
+    screenshots: Screenshots,
     lock_surfaces: HashMap<ObjectId, LockSurface>,
     output_to_lock_surfaces: HashMap<ObjectId, ObjectId>,
+    /// Outputs each lock surface is currently shown on, as told by the
+    /// compositor for it or its indicator subsurface
+    surface_outputs: HashMap<ObjectId, Vec<wl_output::WlOutput>>,
+    primary_surface: Option<ObjectId>,
+    /// Surface last clicked when `primary_follows_click` is set
+    clicked_surface: Option<ObjectId>,
+    /// Surface with keyboard focus, for `indicator.feedback_on`
+    focused_surface: Option<ObjectId>,
     keyboard: KeyboardState,
+    /// Key held down and its repeat timer, when `keyboard.repeat_*` override
+    /// the compositor's repeat settings
+    key_repeat: Option<(u32, RegistrationToken)>,
+    pointer: Option<wl_pointer::WlPointer>,
+    touch: Option<wl_touch::WlTouch>,
+    /// PIN pad key under each touch point, pressed when it is lifted
+    touch_keys: HashMap<i32, Option<(ObjectId, PinKey)>>,
+    cursor_shape_device: Option<WpCursorShapeDeviceV1>,
     lock: Option<SessionLock>,
     password: PasswordBuffer,
     lifecycle: LifeCycle,
@@ -227,24 +393,93 @@ struct State {
     auth_req_send: Option<channel::Sender<PasswordBuffer>>,
     indicator: Indicator,
     clock: Clock,
+    overlay_card: OverlayCard,
+    modifier_badges: ModifierBadges,
+    avatar: Avatar,
     sigusr_received: Arc<AtomicBool>,
+    /// Set on SIGUSR2 or SIGHUP, asking to reload the theme
+    reload_requested: Arc<AtomicBool>,
+    status_socket: StatusSocket,
+    output_debounce: OutputDebounce,
+    /// Failures since the last success, doubling the `backoff_base_secs`
+    /// cooldown each time
+    backoff_failures: u32,
+    /// When the password was submitted, to show "Verifying" for at least
+    /// `min_state_display_ms`
+    verifying_since: Option<Instant>,
+    /// Enter was pressed before the session was locked, the buffered
+    /// password is submitted once it is
+    submit_when_locked: bool,
+    /// Lock surface waiting for the frame callback that drives the next
+    /// redraw, so redraws from elsewhere don't start a second frame loop
+    frame_surface: Option<ObjectId>,
+    /// Something shown changed outside of an animation, redrawn once the
+    /// current events are handled unless a frame callback is pending anyway
+    redraw_requested: bool,
+}
+
+/// Keeps the role object of a lock surface alive
+enum SurfaceRole {
+    Lock(#[allow(dead_code)] SessionLockSurface),
+    #[cfg(feature = "preview")]
+    Preview(#[allow(dead_code)] smithay_client_toolkit::shell::wlr_layer::LayerSurface),
 }
 
 struct LockSurface {
-    _lock_surface: SessionLockSurface,
+    _role: SurfaceRole,
     base_surface: EasySurface,
-    indicator_surface: EasySurface,
+    /// `None` when `render.single_surface` draws everything on the base surface
+    indicator_surface: Option<EasySurface>,
+    /// Background reused across frames in single surface mode
+    background_cache: Option<cairo::ImageSurface>,
+    /// Scale of the output the buffers are drawn at
+    scale_factor: i32,
+    /// Image and mode of the output's `[[output]]` entry or screenshot,
+    /// shown instead of the global background
+    background: Option<(cairo::ImageSurface, BackgroundMode)>,
+    /// Output the surface was created for, standing in for the outputs it is
+    /// shown on until the compositor tells
+    output: wl_output::WlOutput,
+}
+
+impl LockSurface {
+    fn configure(&mut self, shm: &Shm, width: i32, height: i32) {
+        self.base_surface
+            .configure(shm, width, height, self.scale_factor);
+        if let Some(indicator_surface) = self.indicator_surface.as_mut() {
+            indicator_surface.configure(shm, width, height, self.scale_factor);
+        }
+    }
+
+    /// Redraws at `scale_factor` from now on, returning whether it changed
+    fn set_scale_factor(&mut self, shm: &Shm, scale_factor: i32) -> bool {
+        if self.scale_factor == scale_factor {
+            return false;
+        }
+        self.scale_factor = scale_factor;
+        // Fresh buffers at the new scale, once the surface has a size
+        if let Some((width, height)) = self.base_surface.get_size() {
+            self.configure(shm, width, height);
+        }
+        true
+    }
 }
 
 impl CompositorHandler for State {
     fn scale_factor_changed(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _new_factor: i32,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        new_factor: i32,
     ) {
-        // Not needed for this example.
+        if let Some(surface_id) = self.lock_surface_id(surface)
+            && let Some(lock_surface) = self.lock_surfaces.get_mut(&surface_id)
+            && lock_surface.set_scale_factor(&self.shm_state, new_factor)
+        {
+            debug!("Surface {surface_id} moved to scale {new_factor}");
+            self.draw(conn, qh);
+        }
     }
 
     fn transform_changed(
@@ -264,27 +499,59 @@ impl CompositorHandler for State {
         _surface: &wl_surface::WlSurface,
         _time: u32,
     ) {
+        self.frame_surface = None;
         self.draw(conn, qh);
     }
 
     fn surface_enter(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
     ) {
-        // Not needed for this example.
+        // Subsurfaces are shown with their lock surface
+        let Some(surface_id) = self.lock_surface_id(surface) else {
+            return;
+        };
+        let outputs = self.surface_outputs.entry(surface_id.clone()).or_default();
+        if outputs.contains(output) {
+            return;
+        }
+        let first = outputs.is_empty();
+        outputs.push(output.clone());
+        if first
+            && self
+                .lock_surfaces
+                .get(&surface_id)
+                .is_some_and(|lock_surface| &lock_surface.output != output)
+        {
+            // Shown elsewhere than it was created for, take that output's
+            // background instead
+            let background = self.output_background(output);
+            if let Some(lock_surface) = self.lock_surfaces.get_mut(&surface_id) {
+                lock_surface.background = background;
+                lock_surface.background_cache = None;
+                lock_surface.base_surface.invalidate();
+            }
+        }
+        self.surface_outputs_changed(conn, qh, surface_id);
     }
 
     fn surface_leave(
         &mut self,
-        _conn: &Connection,
-        _qh: &QueueHandle<Self>,
-        _surface: &wl_surface::WlSurface,
-        _output: &wl_output::WlOutput,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface: &wl_surface::WlSurface,
+        output: &wl_output::WlOutput,
     ) {
-        // Not needed for this example.
+        let Some(surface_id) = self.lock_surface_id(surface) else {
+            return;
+        };
+        if let Some(outputs) = self.surface_outputs.get_mut(&surface_id) {
+            outputs.retain(|o| o != output);
+        }
+        self.surface_outputs_changed(conn, qh, surface_id);
     }
 }
 
@@ -299,9 +566,12 @@ impl OutputHandler for State {
         qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
-        if let Some(lock) = self.lock.take() {
-            self.create_lock_surface(qh, &lock, output);
-            self.lock = Some(lock);
+        #[cfg(feature = "preview")]
+        if self.config.preview {
+            self.create_preview_surface(qh, output.clone());
+        }
+        if self.lock.is_some() {
+            self.debounce_lock_surface(qh, output);
         }
     }
 
@@ -311,6 +581,7 @@ impl OutputHandler for State {
         _qh: &QueueHandle<Self>,
         _output: wl_output::WlOutput,
     ) {
+        self.update_primary_surface();
     }
 
     fn output_destroyed(
@@ -319,9 +590,19 @@ impl OutputHandler for State {
         _qh: &QueueHandle<Self>,
         output: wl_output::WlOutput,
     ) {
+        self.record_output_disconnect(&output);
         if let Some(surface_id) = self.output_to_lock_surfaces.remove(&output.id()) {
+            if self.frame_surface.as_ref() == Some(&surface_id) {
+                // Its frame callback won't come anymore
+                self.frame_surface = None;
+            }
             self.lock_surfaces.remove(&surface_id);
+            self.surface_outputs.remove(&surface_id);
         }
+        for outputs in self.surface_outputs.values_mut() {
+            outputs.retain(|o| o != &output);
+        }
+        self.update_primary_surface();
     }
 }
 
@@ -346,9 +627,11 @@ impl SeatHandler for State {
         capability: seat::Capability,
     ) {
         if capability == seat::Capability::Keyboard {
-            let keyboard = self
-                .seat_state
-                .get_keyboard_with_repeat(
+            let keyboard = if self.config.keyboard.repeat_override().is_some() {
+                // Repeats are driven by our own timer, see start_key_repeat
+                self.seat_state.get_keyboard::<Self, Self>(qh, &seat, None)
+            } else {
+                self.seat_state.get_keyboard_with_repeat(
                     qh,
                     &seat,
                     None,
@@ -357,8 +640,25 @@ impl SeatHandler for State {
                         state.handle_key_press_or_repeat(event);
                     }),
                 )
-                .expect("Failed to get keyboard");
+            }
+            .expect("Failed to get keyboard");
             self.keyboard = KeyboardState::new(Some(keyboard));
+        } else if capability == seat::Capability::Pointer && self.pointer.is_none() {
+            let pointer = self
+                .seat_state
+                .get_pointer(qh, &seat)
+                .expect("Failed to get pointer");
+            self.cursor_shape_device = self
+                .cursor_shape_manager
+                .as_ref()
+                .map(|manager| manager.get_shape_device(&pointer, qh));
+            self.pointer = Some(pointer);
+        } else if capability == seat::Capability::Touch && self.touch.is_none() {
+            let touch = self
+                .seat_state
+                .get_touch(qh, &seat)
+                .expect("Failed to get touch");
+            self.touch = Some(touch);
         }
     }
 
@@ -367,25 +667,197 @@ impl SeatHandler for State {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _seat: wl_seat::WlSeat,
-        _capability: seat::Capability,
+        capability: seat::Capability,
     ) {
+        if capability == seat::Capability::Keyboard {
+            self.stop_key_repeat();
+            self.keyboard.release();
+            self.keyboard = KeyboardState::new(None);
+        } else if capability == seat::Capability::Pointer {
+            if let Some(device) = self.cursor_shape_device.take() {
+                device.destroy();
+            }
+            if let Some(pointer) = self.pointer.take()
+                && pointer.version() >= 3
+            {
+                pointer.release();
+            }
+        } else if capability == seat::Capability::Touch {
+            self.touch_keys.clear();
+            if let Some(touch) = self.touch.take()
+                && touch.version() >= 3
+            {
+                touch.release();
+            }
+        }
+
+        if capability != seat::Capability::Pointer && !self.keyboard.is_present() {
+            if self.touch.is_some() && (self.config.show_pin_pad || self.config.pin_pad_fallback) {
+                info!("No keyboard left, input goes through the PIN pad");
+            } else {
+                warn!("No keyboard or touchscreen left to unlock with");
+            }
+        }
     }
 
     fn remove_seat(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _seat: wl_seat::WlSeat) {
     }
 }
 
+impl PointerHandler for State {
+    fn pointer_frame(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        pointer: &wl_pointer::WlPointer,
+        events: &[PointerEvent],
+    ) {
+        self.redraw_requested = true;
+        for event in events {
+            match event.kind {
+                PointerEventKind::Enter { serial } => {
+                    // Otherwise the cursor keeps whatever shape the last
+                    // focused application gave it
+                    if self.config.hide_cursor {
+                        pointer.set_cursor(serial, None, 0, 0);
+                    } else if let Some(device) = self.cursor_shape_device.as_ref() {
+                        device.set_shape(serial, Shape::Default);
+                    }
+                }
+                PointerEventKind::Leave { .. } => {}
+                PointerEventKind::Motion { .. }
+                | PointerEventKind::Press { .. }
+                | PointerEventKind::Release { .. }
+                | PointerEventKind::Axis { .. } => {
+                    // Compositors that don't route the pointer to lock
+                    // surfaces never get here, keeping the configured output
+                    if let PointerEventKind::Press { .. } = event.kind
+                        && self.config.primary_follows_click
+                        && let Some(surface_id) = self.lock_surface_id(&event.surface)
+                    {
+                        self.clicked_surface = Some(surface_id);
+                        self.update_primary_surface();
+                    }
+                    if self.lifecycle == LifeCycle::Locked
+                        && self.register_activity(ActivitySource::Pointer)
+                        && self.indicator.input_state == overlay::InputState::Idle
+                    {
+                        self.indicator.set_input_state(overlay::InputState::Neutral);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl TouchHandler for State {
+    fn down(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _serial: u32,
+        _time: u32,
+        surface: wl_surface::WlSurface,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        self.redraw_requested = true;
+        if self.lifecycle != LifeCycle::Locked {
+            return;
+        }
+        self.register_activity(ActivitySource::Touch);
+        if self.indicator.is_asleep {
+            // Like a first key press, only reveals the indicator and PIN pad
+            self.wake_indicator();
+            return;
+        }
+        let key = self.pin_pad_key_at(&surface, position);
+        self.touch_keys.insert(id, key);
+    }
+
+    fn up(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _serial: u32,
+        _time: u32,
+        id: i32,
+    ) {
+        self.redraw_requested = true;
+        // Keys are pressed on release, like buttons
+        if let Some(Some((_, key))) = self.touch_keys.remove(&id) {
+            self.press_pin_key(key);
+        }
+    }
+
+    fn motion(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _time: u32,
+        id: i32,
+        position: (f64, f64),
+    ) {
+        let Some(Some((surface_id, key))) = self.touch_keys.get(&id).cloned() else {
+            return;
+        };
+        // Sliding off a key cancels it
+        let surface = self
+            .lock_surfaces
+            .get(&surface_id)
+            .map(|lock_surface| lock_surface.base_surface.wl_surface().clone());
+        let still_on_key = surface
+            .and_then(|surface| self.pin_pad_key_at(&surface, position))
+            .is_some_and(|(_, current)| current == key);
+        if !still_on_key {
+            self.touch_keys.insert(id, None);
+            self.redraw_requested = true;
+        }
+    }
+
+    fn shape(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _id: i32,
+        _major: f64,
+        _minor: f64,
+    ) {
+    }
+
+    fn orientation(
+        &mut self,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+        _touch: &wl_touch::WlTouch,
+        _id: i32,
+        _orientation: f64,
+    ) {
+    }
+
+    fn cancel(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _touch: &wl_touch::WlTouch) {
+        self.touch_keys.clear();
+        self.redraw_requested = true;
+    }
+}
+
 impl KeyboardHandler for State {
     fn enter(
         &mut self,
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _keyboard: &wl_keyboard::WlKeyboard,
-        _surface: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _serial: u32,
         _raw: &[u32],
         _keysyms: &[keyboard::Keysym],
     ) {
+        self.focused_surface = self.lock_surface_id(surface);
+        self.redraw_requested = true;
     }
 
     fn leave(
@@ -393,9 +865,17 @@ impl KeyboardHandler for State {
         _conn: &Connection,
         _qh: &QueueHandle<Self>,
         _keyboard: &wl_keyboard::WlKeyboard,
-        _surface: &wl_surface::WlSurface,
+        surface: &wl_surface::WlSurface,
         _serial: u32,
     ) {
+        if self.focused_surface == self.lock_surface_id(surface) {
+            self.focused_surface = None;
+            self.redraw_requested = true;
+        }
+        self.stop_key_repeat();
+        if self.config.warn_on_focus_loss && self.lifecycle == LifeCycle::Locked {
+            self.check_focus_after_leave();
+        }
     }
 
     fn press_key(
@@ -406,6 +886,7 @@ impl KeyboardHandler for State {
         _serial: u32,
         event: keyboard::KeyEvent,
     ) {
+        self.start_key_repeat(&event);
         self.handle_key_press_or_repeat(event);
     }
 
@@ -415,8 +896,15 @@ impl KeyboardHandler for State {
         _qh: &QueueHandle<Self>,
         _keyboard: &wl_keyboard::WlKeyboard,
         _serial: u32,
-        _event: keyboard::KeyEvent,
+        event: keyboard::KeyEvent,
     ) {
+        if self
+            .key_repeat
+            .as_ref()
+            .is_some_and(|(raw_code, _)| *raw_code == event.raw_code)
+        {
+            self.stop_key_repeat();
+        }
     }
 
     fn update_modifiers(
@@ -428,9 +916,8 @@ impl KeyboardHandler for State {
         modifiers: keyboard::Modifiers,
         layout: u32,
     ) {
-        self.keyboard.is_caps_lock = modifiers.caps_lock;
-        self.keyboard.is_control = modifiers.ctrl;
-        self.keyboard.set_active_layout(layout);
+        self.keyboard.update_modifiers(modifiers, layout);
+        self.redraw_requested = true;
     }
 
     fn update_keymap(
@@ -441,14 +928,14 @@ impl KeyboardHandler for State {
         keymap: keyboard::Keymap<'_>,
     ) {
         self.keyboard.parse_keymap_layouts(keymap);
+        self.redraw_requested = true;
     }
 }
 
 impl SessionLockHandler for State {
     fn locked(&mut self, _conn: &Connection, qh: &QueueHandle<Self>, session_lock: SessionLock) {
-        for output in self.output_state.outputs() {
-            self.create_lock_surface(qh, &session_lock, output);
-        }
+        audit::record(SecurityEvent::Locked);
+        self.create_lock_surfaces(qh, &session_lock);
         self.lock = Some(session_lock);
     }
 
@@ -458,6 +945,7 @@ impl SessionLockHandler for State {
         _qh: &QueueHandle<Self>,
         _session_lock: SessionLock,
     ) {
+        audit::record(SecurityEvent::LockFailed);
         panic!("Failed to lock session. Is another lock screen running?");
     }
 
@@ -469,15 +957,8 @@ impl SessionLockHandler for State {
         configure: SessionLockSurfaceConfigure,
         _serial: u32,
     ) {
-        let surface_id = surface.wl_surface().id();
-        self.lock_surfaces.entry(surface_id).and_modify(|e| {
-            let (width, height) = configure.new_size;
-            let (width, height) = (width as i32, height as i32);
-            e.base_surface.configure(&self.shm_state, width, height);
-            e.indicator_surface
-                .configure(&self.shm_state, width, height);
-        });
-        self.draw(conn, qh);
+        let (width, height) = configure.new_size;
+        self.configure_lock_surface(conn, qh, surface.wl_surface().id(), width, height);
     }
 }
 
@@ -494,22 +975,36 @@ pub fn daemon(nochdir: bool, noclose: bool) -> Result<(), i32> {
 
 impl State {
     pub fn create_auth_channel(&mut self, event_loop: &mut EventLoop<Self>) {
-        let (auth_req_send, auth_res_recv) = create_and_run_auth_loop();
+        #[cfg(feature = "preview")]
+        let (auth_req_send, auth_res_recv) = if self.config.preview {
+            auth::create_preview_auth_loop()
+        } else {
+            create_and_run_auth_loop(&self.config)
+        };
+        #[cfg(not(feature = "preview"))]
+        let (auth_req_send, auth_res_recv) = create_and_run_auth_loop(&self.config);
         self.auth_req_send = Some(auth_req_send);
         event_loop
             .handle()
             .insert_source(auth_res_recv, |evt, _metadata, state| match evt {
-                channel::Event::Msg(status) => {
-                    if status {
-                        if let Some(lock) = state.lock.take() {
-                            lock.unlock();
-                        }
-                        state.lock_surfaces.clear();
-                        state.lifecycle = LifeCycle::Authenticated;
+                channel::Event::Msg(result) => {
+                    let min_shown =
+                        Duration::from_millis(state.config.indicator.min_state_display_ms);
+                    let shown = state
+                        .verifying_since
+                        .map_or(min_shown, |since| since.elapsed());
+                    if shown < min_shown {
+                        // Keep "Verifying" perceptible however fast PAM answers
+                        let timer = Timer::from_duration(min_shown - shown);
+                        state
+                            .loop_handle
+                            .insert_source(timer, move |_, _, state| {
+                                state.handle_auth_result(result);
+                                TimeoutAction::Drop
+                            })
+                            .unwrap();
                     } else {
-                        state.indicator.auth_state = overlay::AuthState::Invalid;
-                        state.indicator.failed_attempts.inc();
-                        state.indicator.last_update = Instant::now();
+                        state.handle_auth_result(result);
                     }
                 }
                 channel::Event::Closed => {
@@ -521,6 +1016,121 @@ impl State {
             .unwrap();
     }
 
+    fn handle_auth_result(&mut self, result: AuthResult) {
+        self.verifying_since = None;
+        self.redraw_requested = true;
+        let reason = match result {
+            AuthResult::Password(true) => Some("authenticated"),
+            AuthResult::External => Some("external auth"),
+            AuthResult::Password(false) => None,
+        };
+        if let Some(reason) = reason {
+            self.backoff_failures = 0;
+            if self.config.indicator.reset_attempts_on_success {
+                self.indicator.failed_attempts.reset();
+                self.save_failed_attempts();
+            }
+            self.unlock(reason);
+        } else {
+            self.indicator.auth_state = overlay::AuthState::Invalid;
+            self.indicator.failed_attempts.inc();
+            self.backoff_failures += 1;
+            let failed_attempts = self.indicator.failed_attempts.value();
+            audit::record(SecurityEvent::FailedAttempt(failed_attempts));
+            self.indicator.last_update = Instant::now();
+            self.indicator.last_failure = Some(Instant::now());
+            self.save_failed_attempts();
+            self.lockout_if_due();
+        }
+    }
+
+    /// Locks input out for `lockout_secs` every `max_auth_attempts` failures,
+    /// or for the exponential backoff cooldown if that is longer. The backoff
+    /// only starts after `backoff_after_attempts` failures
+    fn lockout_if_due(&mut self) {
+        let max_attempts = self.config.max_auth_attempts;
+        let failed_attempts = self.indicator.failed_attempts.value();
+        let mut lockout_secs = 0;
+        if max_attempts > 0 && failed_attempts > 0 && failed_attempts.is_multiple_of(max_attempts) {
+            lockout_secs = self.config.lockout_secs;
+        }
+        let backoff_failures = self
+            .backoff_failures
+            .saturating_sub(self.config.backoff_after_attempts);
+        if self.config.backoff_base_secs > 0 && backoff_failures > 0 {
+            // base * 2^(failures - 1), saturating long before overflowing
+            let factor = 1u64.checked_shl(backoff_failures - 1).unwrap_or(u64::MAX);
+            let backoff_secs = self
+                .config
+                .backoff_base_secs
+                .saturating_mul(factor)
+                .min(self.config.backoff_max_secs);
+            lockout_secs = lockout_secs.max(backoff_secs);
+        }
+        if lockout_secs > 0 {
+            let lockout = Duration::from_secs(lockout_secs);
+            self.indicator.lockout_until = Some(Instant::now() + lockout);
+        }
+    }
+
+    /// Records a `FocusLost` event unless a lock surface gets the focus back
+    /// shortly, as when it moves between outputs. The session lock protocol
+    /// should never let other surfaces take it
+    fn check_focus_after_leave(&self) {
+        const GRACE: Duration = Duration::from_millis(500);
+        let timer = Timer::from_duration(GRACE);
+        let result = self.loop_handle.insert_source(timer, |_, _, state| {
+            if state.focused_surface.is_none()
+                && state.lifecycle == LifeCycle::Locked
+                && !state.lock_surfaces.is_empty()
+            {
+                audit::record(SecurityEvent::FocusLost);
+            }
+            TimeoutAction::Drop
+        });
+        if let Err(err) = result {
+            error!("Failed to watch the keyboard focus with error {err}");
+        }
+    }
+
+    fn save_failed_attempts(&self) {
+        if let Some(path) = self.config.attempts_file.as_deref() {
+            attempts_file::save(Path::new(path), self.indicator.failed_attempts.value());
+        }
+    }
+
+    /// Releases the lock, after fading out the surfaces if `fade_out_ms` is
+    /// set. `reason` is recorded for auditing
+    pub fn unlock(&mut self, reason: &'static str) {
+        if self.lifecycle != LifeCycle::Locked {
+            return;
+        }
+        audit::record(SecurityEvent::Unlocked(reason));
+        if self.config.fade_out_ms > 0 {
+            self.lifecycle = LifeCycle::FadingOut(Instant::now());
+        } else {
+            self.finish_unlock();
+        }
+    }
+
+    fn finish_unlock(&mut self) {
+        if let Some(lock) = self.lock.take() {
+            lock.unlock();
+        }
+        self.lock_surfaces.clear();
+        self.surface_outputs.clear();
+        self.lifecycle = LifeCycle::Authenticated;
+    }
+
+    /// Opacity of the surfaces while fading out on unlock
+    fn fade_out_alpha(&self) -> Option<f64> {
+        let LifeCycle::FadingOut(start) = self.lifecycle else {
+            return None;
+        };
+        let duration = Duration::from_millis(self.config.fade_out_ms);
+        Some((1.0 - start.elapsed().as_secs_f64() / duration.as_secs_f64()).max(0.0))
+    }
+
     pub fn create_sigusr_interrupt_handler(&self) {
         const SIGUSR1: i32 = 10;
         match signal_hook::flag::register(SIGUSR1, self.sigusr_received.clone()) {
@@ -545,6 +1155,17 @@ impl State {
         }
     }
 
+    /// Creates the lock surfaces of the outputs that have none yet. Called
+    /// when requesting the lock, so they are ready as soon as it is granted,
+    /// then once locked for outputs announced in between, which `new_output`
+    /// leaves alone until there is a lock
+    fn create_lock_surfaces(&mut self, qh: &QueueHandle<Self>, lock: &SessionLock) {
+        let outputs = self.output_state.outputs().collect::<Vec<_>>();
+        for output in outputs {
+            self.create_lock_surface(qh, lock, output);
+        }
+    }
+
     pub fn create_lock_surface(
         &mut self,
         qh: &QueueHandle<Self>,
@@ -552,82 +1173,514 @@ impl State {
         output: wl_output::WlOutput,
     ) {
         if self.output_to_lock_surfaces.contains_key(&output.id()) {
+            debug!("Output {} already has a lock surface", output.id());
+            return;
+        }
+        if self.output_to_lock_surfaces.len() >= self.config.max_outputs {
+            // The compositor keeps outputs without a lock surface blank
+            error!(
+                "Not locking output {} beyond max_outputs ({})",
+                output.id(),
+                self.config.max_outputs
+            );
             return;
         }
 
         let surface = self.compositor_state.create_surface(&qh);
         let lock_surface = lock.create_lock_surface(surface.clone(), &output, &qh);
-        let surface_id = lock_surface.wl_surface().id();
-        let (indicator_subsurface, indicator_surface) = self
-            .subcompositor_state
-            .create_subsurface(lock_surface.wl_surface().clone(), &qh);
+        self.insert_lock_surface(qh, &output, surface, SurfaceRole::Lock(lock_surface));
+    }
 
-        indicator_subsurface.set_sync();
-        indicator_subsurface.set_position(0, 0);
+    /// Adds the indicator subsurface to `surface`, unless rendering to a single
+    /// surface, and tracks it as the lock surface for `output`
+    fn insert_lock_surface(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: &wl_output::WlOutput,
+        surface: wl_surface::WlSurface,
+        role: SurfaceRole,
+    ) {
+        let surface_id = surface.id();
+        let background = self.output_background(output);
+        let indicator_surface = (!self.config.render.single_surface).then(|| {
+            let (indicator_subsurface, indicator_surface) = self
+                .subcompositor_state
+                .create_subsurface(surface.clone(), &qh);
+
+            indicator_subsurface.set_sync();
+            indicator_subsurface.set_position(0, 0);
+            EasySurface::new(indicator_surface, wl_shm::Format::Argb8888)
+        });
 
         self.lock_surfaces.insert(
             surface_id.clone(),
             LockSurface {
-                _lock_surface: lock_surface,
+                _role: role,
                 base_surface: EasySurface::new(surface, wl_shm::Format::Argb8888),
-                indicator_surface: EasySurface::new(indicator_surface, wl_shm::Format::Argb8888),
+                indicator_surface,
+                background_cache: None,
+                scale_factor: self
+                    .output_state
+                    .info(output)
+                    .map_or(1, |info| info.scale_factor),
+                background,
+                output: output.clone(),
             },
         );
         self.output_to_lock_surfaces.insert(output.id(), surface_id);
+        self.update_primary_surface();
+    }
+
+    fn configure_lock_surface(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface_id: ObjectId,
+        width: u32,
+        height: u32,
+    ) {
+        self.lock_surfaces.entry(surface_id).and_modify(|e| {
+            let (width, height) = (width as i32, height as i32);
+            e.configure(&self.shm_state, width, height);
+        });
+        self.draw(conn, qh);
+    }
+
+    /// Outputs the lock surface `surface_id` is shown on; more than one if it
+    /// spans outputs, and the output it was created for until it is mapped
+    pub fn surface_outputs(&self, surface_id: &ObjectId) -> Vec<wl_output::WlOutput> {
+        match self.surface_outputs.get(surface_id) {
+            Some(outputs) if !outputs.is_empty() => outputs.clone(),
+            _ => self
+                .lock_surfaces
+                .get(surface_id)
+                .map(|lock_surface| vec![lock_surface.output.clone()])
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Draws `surface_id` at the largest scale of the outputs it is on, and
+    /// moves the primary surface if its outputs decide it
+    fn surface_outputs_changed(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        surface_id: ObjectId,
+    ) {
+        let scale_factor = self
+            .surface_outputs(&surface_id)
+            .iter()
+            .filter_map(|output| self.output_state.info(output))
+            .map(|info| info.scale_factor)
+            .max();
+        if let Some(scale_factor) = scale_factor
+            && let Some(lock_surface) = self.lock_surfaces.get_mut(&surface_id)
+            && lock_surface.set_scale_factor(&self.shm_state, scale_factor)
+        {
+            debug!("Surface {surface_id} moved to scale {scale_factor}");
+        }
+        self.update_primary_surface();
+        self.draw(conn, qh);
+    }
+
+    /// Lock surface that `surface` is, or is a subsurface of
+    fn lock_surface_id(&self, surface: &wl_surface::WlSurface) -> Option<ObjectId> {
+        let id = surface.id();
+        self.lock_surfaces
+            .iter()
+            .find(|(surface_id, lock_surface)| {
+                **surface_id == id
+                    || lock_surface
+                        .indicator_surface
+                        .as_ref()
+                        .is_some_and(|indicator| indicator.wl_surface().id() == id)
+            })
+            .map(|(surface_id, _)| surface_id.clone())
+    }
+
+    /// Loads the image of the `[[output]]` entry naming `output`, if any,
+    /// else takes the screenshot of `output` taken before locking
+    fn output_background(
+        &mut self,
+        output: &wl_output::WlOutput,
+    ) -> Option<(cairo::ImageSurface, BackgroundMode)> {
+        let screenshot = self
+            .screenshots
+            .take(output)
+            .map(|image| (image, BackgroundMode::Screenshot));
+        let info = self.output_state.info(output);
+        let Some(entry) = self.config.output.iter().find(|entry| {
+            info.as_ref().is_some_and(|info| {
+                info.name.as_ref() == Some(&entry.name)
+                    || info.description.as_ref() == Some(&entry.name)
+            })
+        }) else {
+            return screenshot;
+        };
+        match try_load_image(&entry.image, self.config.max_image_pixels) {
+            Ok(image) => Some((image, entry.mode.unwrap_or(self.config.background_mode))),
+            Err(err) => {
+                error!(
+                    "Failed to load {} for output {} with error {err}, using the global background",
+                    entry.image, entry.name
+                );
+                screenshot
+            }
+        }
+    }
+
+    /// Picks the surface that hosts single-instance overlays: the last
+    /// clicked one with `primary_follows_click`, else the one on
+    /// `primary_output` if it is connected, otherwise the first output
+    fn update_primary_surface(&mut self) {
+        if let Some(clicked) = self.clicked_surface.as_ref()
+            && self.lock_surfaces.contains_key(clicked)
+        {
+            self.primary_surface = Some(clicked.clone());
+            return;
+        }
+        let surfaces = self
+            .output_state
+            .outputs()
+            .filter_map(|output| self.output_to_lock_surfaces.get(&output.id()))
+            .cloned()
+            .collect::<Vec<_>>();
+        // The surface shown on that output, wherever it was created
+        let configured = self.config.primary_output.as_ref().and_then(|name| {
+            surfaces.iter().find(|surface_id| {
+                self.surface_outputs(surface_id).iter().any(|output| {
+                    self.output_state.info(output).is_some_and(|info| {
+                        info.name.as_ref() == Some(name) || info.description.as_ref() == Some(name)
+                    })
+                })
+            })
+        });
+        self.primary_surface = configured.or(surfaces.first()).cloned();
+    }
+
+    /// Resets the indicator's idle timer if `source` is configured to count as
+    /// activity. Returns whether it did.
+    pub fn register_activity(&mut self, source: ActivitySource) -> bool {
+        if self.config.activity_sources.contains(&source) {
+            self.indicator.last_update = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Repeats `event` at the configured rate while it is held, if repeat
+    /// settings are overridden
+    fn start_key_repeat(&mut self, event: &keyboard::KeyEvent) {
+        let Some((delay, rate)) = self.config.keyboard.repeat_override() else {
+            return;
+        };
+        self.stop_key_repeat();
+        if rate == 0 || !self.keyboard.key_repeats(event.raw_code) {
+            return;
+        }
+
+        let interval = Duration::from_secs(1) / rate;
+        let repeated = event.clone();
+        let token = self
+            .loop_handle
+            .insert_source(Timer::from_duration(delay), move |_, _, state| {
+                state.handle_key_press_or_repeat(repeated.clone());
+                TimeoutAction::ToDuration(interval)
+            })
+            .expect("Failed to insert key repeat timer");
+        self.key_repeat = Some((event.raw_code, token));
+    }
+
+    fn stop_key_repeat(&mut self) {
+        if let Some((_, token)) = self.key_repeat.take() {
+            self.loop_handle.remove(token);
+        }
+    }
+
+    /// Whether keys edit the password. Those typed while initializing are
+    /// buffered, so a fast typist's first characters aren't lost
+    fn accepts_input(&self) -> bool {
+        matches!(self.lifecycle, LifeCycle::Initing | LifeCycle::Locked)
     }
 
     pub fn handle_key_press_or_repeat(&mut self, event: keyboard::KeyEvent) {
+        if !self.accepts_input() {
+            return;
+        }
+        self.redraw_requested = true;
+        if self.indicator.is_asleep {
+            // With `cold_start` the first key only reveals the indicator and
+            // isn't part of the password
+            self.wake_indicator();
+            return;
+        }
+        if self.indicator.lockout_remaining().is_some() {
+            return;
+        }
+
+        // Some keymaps deliver printable keys without text in some states
+        let input = event.utf8.or_else(|| {
+            let keysym_fallback = self.config.keyboard.keysym_fallback;
+            let input = keysym_fallback.then(|| keyboard_state::keysym_char(event.keysym));
+            input.flatten().map(String::from)
+        });
+        let clears = event.keysym == keyboard::Keysym::Escape
+            && self.config.escape_action == EscapeAction::Clear;
+        // Line editing shortcuts of shells: Ctrl+U clears, Ctrl+W erases a word
+        let shortcut = match event.keysym {
+            _ if !self.keyboard.is_control => None,
+            keyboard::Keysym::u | keyboard::Keysym::U => Some(keyboard::Keysym::u),
+            keyboard::Keysym::w | keyboard::Keysym::W => Some(keyboard::Keysym::w),
+            _ => None,
+        };
+        let source = if event.keysym == keyboard::Keysym::Return
+            || event.keysym == keyboard::Keysym::BackSpace
+            || clears
+            || shortcut.is_some()
+            || input.is_some()
+        {
+            ActivitySource::Key
+        } else {
+            ActivitySource::Modifier
+        };
+        if !self.register_activity(source) && source == ActivitySource::Modifier {
+            // Such keys only ever change the indicator, so ignore them entirely
+            return;
+        }
+
         if event.keysym == keyboard::Keysym::Return {
-            if self.config.ignore_empty_password && self.password.unsecure().len() == 0 {
-                // pass
-            } else if self.indicator.auth_state == overlay::AuthState::Validating {
-                // pass
-            } else {
-                let password = self.password.take();
-                self.auth_req_send.as_ref().unwrap().send(password).unwrap();
-                self.indicator.auth_state = overlay::AuthState::Validating;
-                self.indicator.input_state = overlay::InputState::Idle;
-            }
+            self.submit_password();
         } else if event.keysym == keyboard::Keysym::BackSpace {
-            self.password.backspace();
-            self.indicator.input_state = if self.password.unsecure().len() == 0 {
+            self.erase_input();
+        } else if shortcut == Some(keyboard::Keysym::u) {
+            self.clear_input();
+        } else if shortcut == Some(keyboard::Keysym::w) {
+            self.erase_word();
+        } else if event.keysym == keyboard::Keysym::Escape {
+            match self.config.escape_action {
+                EscapeAction::Clear => self.clear_input(),
+                // Only a preview may be closed without authenticating
+                EscapeAction::Quit if self.config.preview => self.unlock("Escape"),
+                EscapeAction::Quit | EscapeAction::Ignore => {
+                    self.indicator.set_input_state(overlay::InputState::Neutral)
+                }
+            }
+        } else if let Some(input) = input {
+            self.type_input(input);
+        } else {
+            self.indicator.set_input_state(overlay::InputState::Neutral);
+        }
+        self.indicator.move_highlight();
+    }
+
+    /// Applies a key of the touch PIN pad like the matching keyboard key
+    fn press_pin_key(&mut self, key: PinKey) {
+        if !self.accepts_input() || self.indicator.lockout_remaining().is_some() {
+            return;
+        }
+        match key {
+            PinKey::Digit(digit) => self.type_input(digit.to_string()),
+            PinKey::Backspace => self.erase_input(),
+            PinKey::Enter => self.submit_password(),
+        }
+        self.indicator.move_highlight();
+    }
+
+    fn wake_indicator(&mut self) {
+        self.indicator.is_asleep = false;
+        self.indicator.last_update = Instant::now();
+        self.indicator.visible_until = Some(Instant::now() + overlay::IDLE_TIMEOUT);
+    }
+
+    fn submit_password(&mut self) {
+        if self.lifecycle == LifeCycle::Initing {
+            self.submit_when_locked = true;
+            return;
+        }
+        // The password is ignored when the external command replaces PAM
+        let replace_pam = self.config.external_auth_command.is_some()
+            && self.config.external_auth_mode == ExternalAuthMode::Replace;
+        if self.config.ignore_empty_password && self.password.unsecure().is_empty() && !replace_pam
+        {
+            // pass
+        } else if self.indicator.auth_state == overlay::AuthState::Validating {
+            // pass
+        } else {
+            let password = self.password.take();
+            self.indicator.last_char = None;
+            self.auth_req_send.as_ref().unwrap().send(password).unwrap();
+            self.indicator.auth_state = overlay::AuthState::Validating;
+            self.indicator.set_input_state(overlay::InputState::Idle);
+            self.verifying_since = Some(Instant::now());
+        }
+    }
+
+    fn erase_input(&mut self) {
+        self.indicator.last_char = None;
+        self.password.backspace();
+        self.show_erased_input();
+    }
+
+    fn erase_word(&mut self) {
+        self.indicator.last_char = None;
+        self.password.backspace_word();
+        self.show_erased_input();
+    }
+
+    /// Shows "Cleared" once nothing is left, else the backspace highlight
+    fn show_erased_input(&mut self) {
+        self.indicator
+            .set_input_state(if self.password.unsecure().is_empty() {
                 overlay::InputState::Clear
             } else {
                 overlay::InputState::Backspace
-            };
-        } else if let Some(input) = event.utf8 {
-            self.password.append(input);
-            self.indicator.input_state = overlay::InputState::Letter;
-        } else {
-            self.indicator.input_state = overlay::InputState::Neutral;
+            });
+    }
+
+    fn clear_input(&mut self) {
+        self.indicator.last_char = None;
+        self.password.clear();
+        self.indicator.set_input_state(overlay::InputState::Clear);
+    }
+
+    fn type_input(&mut self, input: String) {
+        if self.config.indicator.reveal_last_char {
+            self.indicator.last_char = input.chars().last().map(|c| (c, Instant::now()));
         }
-        self.indicator.highlight_start = rand::random::<u32>() % 2048;
-        self.indicator.last_update = Instant::now();
+        self.password.append(input);
+        self.indicator.set_input_state(overlay::InputState::Letter);
+
+        let auto_submit_length = self.config.auto_submit_length as usize;
+        if auto_submit_length > 0 && self.password.unsecure().chars().count() >= auto_submit_length
+        {
+            self.submit_password();
+        }
+    }
+
+    /// PIN pad key under a touch point, and the lock surface it is on
+    fn pin_pad_key_at(
+        &mut self,
+        surface: &wl_surface::WlSurface,
+        (x, y): (f64, f64),
+    ) -> Option<(ObjectId, PinKey)> {
+        let surface_id = self.lock_surface_id(surface)?;
+        let (width, height) = self
+            .lock_surfaces
+            .get(&surface_id)?
+            .base_surface
+            .get_size()?;
+        let is_primary = self.primary_surface.as_ref() == Some(&surface_id);
+        let scale_factor = self.lock_surfaces.get(&surface_id)?.scale_factor;
+        let key = self
+            .scene()
+            .pin_pad_key_at(x, y, width, height, is_primary, scale_factor)?;
+        Some((surface_id, key))
     }
 
     pub fn draw(&mut self, _conn: &Connection, qh: &QueueHandle<Self>) {
-        if Instant::now() - self.indicator.last_update >= Duration::from_secs(3) {
+        if Instant::now() - self.indicator.last_update >= overlay::IDLE_TIMEOUT {
             self.indicator.input_state = overlay::InputState::Idle;
-            self.indicator.auth_state = overlay::AuthState::Idle;
+            // Verifying lasts until PAM answers, however stale the activity
+            // is, so that Enter can't queue another request meanwhile
+            if self.indicator.auth_state != overlay::AuthState::Validating {
+                self.indicator.auth_state = overlay::AuthState::Idle;
+            }
         }
-        let mut requested_reframe = false;
-        for lock_surface in &mut self.lock_surfaces.values_mut() {
-            let rendered = lock_surface.indicator_surface.render(
-                qh,
-                !requested_reframe,
-                |_buffer, canvas, width, height, _resized| {
-                    let stride = width * 4;
-                    let cairo_surface = unsafe {
-                        cairo::ImageSurface::create_for_data_unsafe(
-                            canvas.first_mut().unwrap(),
-                            cairo::Format::ARgb32,
+        self.indicator.update_pending_input_state();
+        self.indicator.input_len = self.password.unsecure().chars().count();
+        if self
+            .indicator
+            .last_char
+            .is_some_and(|(_, at)| at.elapsed() >= overlay::REVEAL_CHAR_DURATION)
+        {
+            // Don't keep a piece of the password around longer than shown
+            self.indicator.last_char = None;
+        }
+        self.update_slideshow_crossfade();
+        let crossfading = self.slideshow.is_crossfading();
+        let fade_out_alpha = self.fade_out_alpha();
+        // Otherwise the frame loop stops until input or the clock redraws
+        let animating = crossfading || fade_out_alpha.is_some() || self.indicator.is_animating();
+        let dither_bits = self.config.render.dither.bits();
+        let mut lock_surfaces = std::mem::take(&mut self.lock_surfaces);
+        let mut frame_surface = self.frame_surface.take();
+        for (surface_id, lock_surface) in lock_surfaces.iter_mut() {
+            if lock_surface.base_surface.get_size().is_none() {
+                // Drawn once the compositor sends the first configure
+                debug!("Skipping draw of surface {surface_id} that has no size yet");
+                continue;
+            }
+            let is_primary = self.primary_surface.as_ref() == Some(surface_id);
+            let is_focused = self.focused_surface.as_ref() == Some(surface_id);
+            let scale_factor = lock_surface.scale_factor;
+            let background = lock_surface.background.as_ref();
+
+            let Some(indicator_surface) = lock_surface.indicator_surface.as_mut() else {
+                // Single surface: composite the overlays over the cached
+                // background on every frame
+                let background_cache = &mut lock_surface.background_cache;
+                let rendered = lock_surface.base_surface.render(
+                    qh,
+                    animating && frame_surface.is_none(),
+                    |_buffer, canvas, width, height, scale, _resized| {
+                        let background = background_cache
+                            .take()
+                            .filter(|image| {
+                                !crossfading
+                                    && image.width() == width * scale
+                                    && image.height() == height * scale
+                            })
+                            .unwrap_or_else(|| {
+                                let image = cairo::ImageSurface::create(
+                                    cairo::Format::ARgb32,
+                                    width * scale,
+                                    height * scale,
+                                )
+                                .unwrap();
+                                image.set_device_scale(scale as f64, scale as f64);
+                                self.output_scene(background).draw_background(
+                                    &cairo::Context::new(&image).unwrap(),
+                                    width,
+                                    height,
+                                    is_primary,
+                                );
+                                image
+                            });
+                        let context = canvas_context(canvas, width, height, scale);
+                        context.save().unwrap();
+                        context.set_operator(cairo::Operator::Source);
+                        context.set_source_surface(&background, 0.0, 0.0).unwrap();
+                        context.paint().unwrap();
+                        context.restore().unwrap();
+                        *background_cache = Some(background);
+
+                        self.scene().draw_overlays(
+                            &context,
                             width,
                             height,
-                            stride,
-                        )
-                        .unwrap()
-                    };
-                    let context = cairo::Context::new(&cairo_surface).unwrap();
+                            is_primary,
+                            is_focused,
+                            scale_factor,
+                        );
+                        if let Some(alpha) = fade_out_alpha {
+                            fade_out(&context, alpha);
+                        }
+                        drop(context);
+                        dither_canvas(canvas, width * scale, height * scale, dither_bits);
+                    },
+                );
+                if rendered && animating && frame_surface.is_none() {
+                    frame_surface = Some(surface_id.clone());
+                }
+                continue;
+            };
+
+            let rendered = indicator_surface.render(
+                qh,
+                animating && frame_surface.is_none(),
+                |_buffer, canvas, width, height, scale, _resized| {
+                    let context = canvas_context(canvas, width, height, scale);
 
                     // Clear
                     context.save().unwrap();
@@ -636,60 +1689,140 @@ impl State {
                     context.paint().unwrap();
                     context.restore().unwrap();
 
-                    if self.config.show_indicator {
-                        self.indicator
-                            .draw(&context, width, height, 1.0, &self.keyboard);
-                    }
-                    if self.config.show_clock {
-                        self.clock.draw(&context, width, height, 1.0);
+                    self.scene().draw_overlays(
+                        &context,
+                        width,
+                        height,
+                        is_primary,
+                        is_focused,
+                        scale_factor,
+                    );
+                    if let Some(alpha) = fade_out_alpha {
+                        fade_out(&context, alpha);
                     }
+                    drop(context);
+                    dither_canvas(canvas, width * scale, height * scale, dither_bits);
                 },
             );
-            requested_reframe = requested_reframe || rendered;
+            if rendered && animating && frame_surface.is_none() {
+                frame_surface = Some(surface_id.clone());
+            }
 
             let rendered = lock_surface.base_surface.render(
                 qh,
-                !requested_reframe,
-                |_buffer, canvas, width, height, resized| {
-                    if resized {
-                        let stride = width * 4;
-                        let cairo_surface = unsafe {
-                            cairo::ImageSurface::create_for_data_unsafe(
-                                canvas.first_mut().unwrap(),
-                                cairo::Format::ARgb32,
-                                width,
-                                height,
-                                stride,
-                            )
-                            .unwrap()
-                        };
-                        let context = cairo::Context::new(&cairo_surface).unwrap();
-                        context.set_antialias(cairo::Antialias::Best);
-                        context.save().unwrap();
-
-                        context.set_operator(cairo::Operator::Source);
-                        context.set_source_color(&self.config.background_color);
-                        context.paint().unwrap();
-                        context.save().unwrap();
-
-                        context.set_operator(cairo::Operator::Over);
-                        if let Some(image) = self.background_image.as_ref() {
-                            render_background_image(
-                                &context,
-                                &image,
-                                self.config.background_mode,
-                                width,
-                                height,
-                            );
+                animating && frame_surface.is_none(),
+                |_buffer, canvas, width, height, scale, resized| {
+                    if resized || crossfading || fade_out_alpha.is_some() {
+                        let context = canvas_context(canvas, width, height, scale);
+                        self.output_scene(background)
+                            .draw_background(&context, width, height, is_primary);
+                        if let Some(alpha) = fade_out_alpha {
+                            fade_out(&context, alpha);
                         }
-                        context.restore().unwrap();
-                        context.identity_matrix();
+                        drop(context);
+                        dither_canvas(canvas, width * scale, height * scale, dither_bits);
                     }
                 },
             );
-            requested_reframe = requested_reframe || rendered;
+            if rendered && animating && frame_surface.is_none() {
+                frame_surface = Some(surface_id.clone());
+            }
+        }
+        self.lock_surfaces = lock_surfaces;
+        self.frame_surface = frame_surface;
+    }
+
+    /// Redraws as the clock changes, on the second or minute boundary, as
+    /// frames are only requested while something animates. Always running,
+    /// so that a theme reload can turn the clock on
+    fn create_clock_tick(&self, conn: &Connection, qh: &QueueHandle<Self>) {
+        let (conn, qh) = (conn.clone(), qh.clone());
+        let timer = Timer::from_duration(self.clock.until_next_tick());
+        self.loop_handle
+            .insert_source(timer, move |_, _, state| {
+                if state.config.show_clock {
+                    state.draw(&conn, &qh);
+                }
+                TimeoutAction::ToDuration(state.clock.until_next_tick())
+            })
+            .unwrap();
+    }
+
+    /// Redraws the background of every lock surface on their next frames
+    fn invalidate_backgrounds(&mut self) {
+        for lock_surface in self.lock_surfaces.values_mut() {
+            lock_surface.base_surface.invalidate();
+            lock_surface.background_cache = None;
+        }
+        self.redraw_requested = true;
+    }
+
+    /// Scene of a lock surface, showing its own `background` if it has one
+    fn output_scene<'a>(
+        &'a mut self,
+        background: Option<&'a (cairo::ImageSurface, BackgroundMode)>,
+    ) -> Scene<'a> {
+        let mut scene = self.scene();
+        if let Some((image, mode)) = background {
+            scene.background_image = Some(image);
+            scene.background_mode = *mode;
+            scene.crossfade_from = None;
         }
+        scene
     }
+
+    /// Borrows what is drawn on every lock surface
+    fn scene(&mut self) -> Scene<'_> {
+        Scene {
+            config: &self.config,
+            background_image: self.background_image.as_ref(),
+            background_mode: self.config.background_mode,
+            crossfade_from: self
+                .slideshow
+                .crossfade(Duration::from_millis(self.config.slideshow_crossfade_ms)),
+            indicator: &self.indicator,
+            clock: &self.clock,
+            overlay_card: &self.overlay_card,
+            modifier_badges: &self.modifier_badges,
+            avatar: &self.avatar,
+            keyboard: &self.keyboard,
+            has_touch: self.touch.is_some(),
+        }
+    }
+}
+
+/// Scales the opacity of everything drawn so far by `alpha`
+fn fade_out(context: &cairo::Context, alpha: f64) {
+    context.save().unwrap();
+    context.set_operator(cairo::Operator::DestIn);
+    context.set_source_rgba(0.0, 0.0, 0.0, alpha);
+    context.paint().unwrap();
+    context.restore().unwrap();
+}
+
+/// Dithers a drawn canvas to `render.dither.bits`, if enabled
+fn dither_canvas(canvas: &mut [u8], width: i32, height: i32, bits: Option<u32>) {
+    if let Some(bits) = bits {
+        dither::dither(canvas, width as usize, height as usize, bits);
+    }
+}
+
+/// Creates a cairo context drawing into a shm buffer canvas, `scale` times
+/// larger than the `width`x`height` surface coordinates it is drawn in
+fn canvas_context(canvas: &mut [u8], width: i32, height: i32, scale: i32) -> cairo::Context {
+    let stride = width * scale * 4;
+    let cairo_surface = unsafe {
+        cairo::ImageSurface::create_for_data_unsafe(
+            canvas.first_mut().unwrap(),
+            cairo::Format::ARgb32,
+            width * scale,
+            height * scale,
+            stride,
+        )
+        .unwrap()
+    };
+    cairo_surface.set_device_scale(scale as f64, scale as f64);
+    cairo::Context::new(&cairo_surface).unwrap()
 }
 
 delegate_compositor!(State);
@@ -700,6 +1833,8 @@ delegate_session_lock!(State);
 
 delegate_seat!(State);
 delegate_keyboard!(State);
+delegate_pointer!(State);
+delegate_touch!(State);
 
 delegate_registry!(State);
 
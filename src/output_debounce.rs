@@ -0,0 +1,87 @@
+//! Delays the lock surfaces of outputs connected while locked, so that a
+//! flaky cable toggling an output doesn't create a surface on every blip. The
+//! compositor keeps such outputs blank until their surface exists.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+use smithay_client_toolkit::reexports::calloop::timer::{TimeoutAction, Timer};
+use wayland_client::{QueueHandle, protocol::wl_output};
+
+use crate::State;
+
+/// Reconnections within this long of a disconnection are logged as churn
+const CHURN_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Default)]
+pub struct OutputDebounce {
+    /// When each output, by name, was last disconnected and how many times it
+    /// reconnected soon after
+    disconnected: HashMap<String, (Instant, u32)>,
+}
+
+impl State {
+    /// Creates the lock surface of a newly connected output once it stayed
+    /// connected for `output_debounce_ms`
+    pub fn debounce_lock_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        self.log_output_churn(&output);
+        let delay = Duration::from_millis(self.config.output_debounce_ms);
+        if delay.is_zero() {
+            self.create_debounced_lock_surface(qh, &output);
+            return;
+        }
+        let qh = qh.clone();
+        self.loop_handle
+            .insert_source(Timer::from_duration(delay), move |_, _, state| {
+                state.create_debounced_lock_surface(&qh, &output);
+                TimeoutAction::Drop
+            })
+            .expect("Failed to insert output debounce timer");
+    }
+
+    fn create_debounced_lock_surface(
+        &mut self,
+        qh: &QueueHandle<Self>,
+        output: &wl_output::WlOutput,
+    ) {
+        if !self.output_state.outputs().any(|o| &o == output) {
+            debug!("Output disconnected again before its lock surface was created");
+            return;
+        }
+        // Unlocked meanwhile otherwise
+        if let Some(lock) = self.lock.take() {
+            self.create_lock_surface(qh, &lock, output.clone());
+            self.lock = Some(lock);
+        }
+    }
+
+    pub fn record_output_disconnect(&mut self, output: &wl_output::WlOutput) {
+        let Some(name) = self.output_state.info(output).and_then(|info| info.name) else {
+            return;
+        };
+        let disconnected = &mut self.output_debounce.disconnected;
+        let reconnects = disconnected.get(&name).map_or(0, |(_, count)| *count);
+        disconnected.insert(name, (Instant::now(), reconnects));
+    }
+
+    fn log_output_churn(&mut self, output: &wl_output::WlOutput) {
+        let Some(name) = self.output_state.info(output).and_then(|info| info.name) else {
+            return;
+        };
+        let Some((at, reconnects)) = self.output_debounce.disconnected.get_mut(&name) else {
+            return;
+        };
+        if at.elapsed() > CHURN_WINDOW {
+            *reconnects = 0;
+            return;
+        }
+        *reconnects += 1;
+        warn!(
+            "Output {name} reconnected {} ms after disconnecting ({} times in a row), \
+             its connection may be unreliable",
+            at.elapsed().as_millis(),
+            reconnects
+        );
+    }
+}
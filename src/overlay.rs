@@ -1,7 +1,14 @@
-use std::time::Instant;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use log::error;
+use time_tz::{OffsetDateTimeExt, Tz};
 
 use crate::CairoExtras;
-use crate::config;
+use crate::cairo_extras::TextAnchor;
+use crate::config::{self, ClockPosition};
+use crate::font_pattern;
 use crate::keyboard_state::KeyboardState;
 
 /// Indicator state: status of authentication attempt
@@ -30,6 +37,68 @@ pub enum InputState {
     Neutral,
 }
 
+/// Key of the touch PIN pad
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PinKey {
+    Digit(char),
+    Backspace,
+    Enter,
+}
+
+impl PinKey {
+    /// Keys row by row, like a phone keypad
+    const LAYOUT: [[PinKey; 3]; 4] = [
+        [PinKey::Digit('1'), PinKey::Digit('2'), PinKey::Digit('3')],
+        [PinKey::Digit('4'), PinKey::Digit('5'), PinKey::Digit('6')],
+        [PinKey::Digit('7'), PinKey::Digit('8'), PinKey::Digit('9')],
+        [PinKey::Backspace, PinKey::Digit('0'), PinKey::Enter],
+    ];
+
+    fn label(&self) -> Cow<'static, str> {
+        match self {
+            PinKey::Digit(digit) => Cow::Owned(digit.to_string()),
+            PinKey::Backspace => Cow::Borrowed("Del"),
+            PinKey::Enter => Cow::Borrowed("OK"),
+        }
+    }
+}
+
+/// What elements may need to know about the session when drawing
+pub struct DrawContext<'a> {
+    pub keyboard: &'a KeyboardState,
+    pub failed_attempts: u32,
+    /// Whether the surface drawn has keyboard focus
+    pub is_focused: bool,
+    /// Whether the PIN pad is drawn below the indicator
+    pub has_pin_pad: bool,
+    /// `keyboard.layout_names`
+    pub layout_names: &'a HashMap<String, String>,
+    /// Factor configured font sizes are multiplied by, see `font_scaling`
+    pub font_scale: f64,
+}
+
+/// An overlay drawn over the background, composed by `overlay.order`
+pub trait Drawable {
+    /// Area the element covers, used to size the overlay card
+    fn bounds(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    ) -> cairo::Rectangle;
+
+    fn draw(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    );
+}
+
 pub struct AttemptsCounter {
     value: u32,
     value_str: String,
@@ -49,90 +118,454 @@ impl AttemptsCounter {
 
     pub fn inc(&mut self) {
         if self.value < 1000 {
-            self.value += 1;
-            self.value_str = if self.value > 999 {
-                "999+".to_string()
-            } else {
-                format!("{}", self.value)
-            };
+            self.set(self.value + 1);
         }
     }
 
+    /// Sets the count, e.g. when restored from `attempts_file`
+    pub fn set(&mut self, value: u32) {
+        self.value = value.min(1000);
+        self.value_str = if self.value == 0 {
+            "".to_string()
+        } else if self.value > 999 {
+            "999+".to_string()
+        } else {
+            format!("{}", self.value)
+        };
+    }
+
+    pub fn reset(&mut self) {
+        self.set(0);
+    }
+
     pub fn format(&self) -> &str {
         &self.value_str
     }
 }
 
+/// Time without input after which the indicator goes back to idle
+pub const IDLE_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long `reveal_last_char` shows a typed character
+pub const REVEAL_CHAR_DURATION: Duration = Duration::from_millis(500);
+
+/// Space left between the clock and the edge it is anchored to, in pixels
+const CLOCK_EDGE_MARGIN: f64 = 16.0;
+
 pub struct Indicator {
     pub config: config::Indicator,
     pub input_state: InputState,
     pub auth_state: AuthState,
-    pub is_caps_lock: bool,
     pub last_update: Instant,
     pub highlight_start: u32,
     pub failed_attempts: AttemptsCounter,
+    /// When the last attempt failed, to hide the count after
+    /// `attempts_display_timeout_secs`
+    pub last_failure: Option<Instant>,
+    /// Input is ignored until then after too many failed attempts
+    pub lockout_until: Option<Instant>,
+    /// Shown even if idle until then, see `show_on_lock_secs`
+    pub visible_until: Option<Instant>,
+    /// Set until the lock is ready, input is ignored meanwhile
+    pub is_initializing: bool,
+    /// Last typed character and when, with `reveal_last_char`
+    pub last_char: Option<(char, Instant)>,
+    /// Hidden until the first key press with `cold_start`
+    pub is_asleep: bool,
+    /// Shown while idle, set through `command_fifo`
+    pub message: Option<String>,
+    /// Characters of the password typed so far, for `auth_prompt_text` and
+    /// `show_password_dots`
+    pub input_len: usize,
+    /// When `input_state` last changed
+    input_state_since: Instant,
+    /// State replacing a transient one once shown `min_state_display_ms`
+    pending_input_state: Option<InputState>,
 }
 
 fn configure_font_drawing(context: &cairo::Context, font: &str, font_size: f64) {
     let mut font_options = context.font_options().unwrap();
     font_options.set_hint_style(cairo::HintStyle::Full);
     context.set_font_options(&font_options);
-    context.select_font_face(font, cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+    match font_pattern::is_pattern(font)
+        .then(|| font_pattern::font_face(font))
+        .flatten()
+    {
+        Some(face) => context.set_font_face(&face),
+        None => context.select_font_face(font, cairo::FontSlant::Normal, cairo::FontWeight::Normal),
+    }
     context.set_font_size(font_size);
 }
 
+/// Random position of the typing highlight, out of 2048 around the ring
+#[cfg(all(feature = "rand", not(feature = "deterministic-highlight")))]
+fn next_highlight_start(_previous: u32) -> u32 {
+    rand::random::<u32>() % 2048
+}
+
+/// Next position of the typing highlight from a full-period LCG over the 2048
+/// around the ring, jumping far enough to look random
+#[cfg(any(not(feature = "rand"), feature = "deterministic-highlight"))]
+fn next_highlight_start(previous: u32) -> u32 {
+    (previous * 1021 + 1237) % 2048
+}
+
 impl Indicator {
-    fn set_color_for_state(&self, context: &cairo::Context, colorset: &config::ColorSet) {
-        if self.input_state == InputState::Clear {
+    pub fn new(config: config::Indicator) -> Self {
+        Self {
+            config,
+            input_state: InputState::Idle,
+            auth_state: AuthState::Idle,
+            last_update: Instant::now(),
+            highlight_start: 0,
+            failed_attempts: AttemptsCounter::new(),
+            last_failure: None,
+            lockout_until: None,
+            visible_until: None,
+            is_initializing: false,
+            last_char: None,
+            is_asleep: false,
+            message: None,
+            input_len: 0,
+            input_state_since: Instant::now(),
+            pending_input_state: None,
+        }
+    }
+
+    /// Moves the typing highlight to another spot on the ring
+    pub fn move_highlight(&mut self) {
+        self.highlight_start = next_highlight_start(self.highlight_start);
+    }
+
+    /// Time left before input is accepted again, if locked out
+    pub fn lockout_remaining(&self) -> Option<Duration> {
+        self.lockout_until
+            .and_then(|until| until.checked_duration_since(Instant::now()))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    /// Whether the indicator changes with time alone, so that it needs
+    /// redrawing on every frame rather than only on input
+    pub fn is_animating(&self) -> bool {
+        let hue_cycle = &self.config.hue_cycle;
+        let attempts_timeout = Duration::from_secs(self.config.attempts_display_timeout_secs);
+        self.auth_state != AuthState::Idle
+            || self.input_state != InputState::Idle
+            || self.pending_input_state.is_some()
+            || self.lockout_remaining().is_some()
+            || self
+                .visible_until
+                .is_some_and(|until| Instant::now() < until)
+            || self.last_char.is_some()
+            || self
+                .last_failure
+                .is_some_and(|at| !attempts_timeout.is_zero() && at.elapsed() < attempts_timeout)
+            || (hue_cycle.degrees > 0.0 && hue_cycle.period_secs > 0.0)
+    }
+
+    /// Shows `state`, or defers it while the transient "Cleared" or neutral
+    /// state hasn't been shown for `min_state_display_ms`, so it doesn't
+    /// flash by unnoticed
+    pub fn set_input_state(&mut self, state: InputState) {
+        let transient = matches!(self.input_state, InputState::Clear | InputState::Neutral);
+        if transient && state != self.input_state && !self.has_shown_input_state() {
+            self.pending_input_state = Some(state);
+        } else {
+            self.pending_input_state = None;
+            self.input_state = state;
+            self.input_state_since = Instant::now();
+        }
+    }
+
+    /// Shows the state deferred by `set_input_state` once due
+    pub fn update_pending_input_state(&mut self) {
+        if self.pending_input_state.is_some() && self.has_shown_input_state() {
+            self.input_state = self.pending_input_state.take().unwrap();
+            self.input_state_since = Instant::now();
+        }
+    }
+
+    fn has_shown_input_state(&self) -> bool {
+        self.input_state_since.elapsed() >= Duration::from_millis(self.config.min_state_display_ms)
+    }
+
+    /// Draws `text` in the current font, boxed and centered below `top`.
+    /// Returns the bottom of the box
+    fn draw_label_box(
+        &self,
+        context: &cairo::Context,
+        text: &str,
+        xc: f64,
+        top: f64,
+        scale: f64,
+        state: &DrawContext,
+    ) -> f64 {
+        let font_extents = context.font_extents().unwrap();
+        let box_padding = font_extents.height() * 0.2 * scale;
+        let y = top + box_padding * 2.0;
+        let text_box = context.text_bounds_anchored(text, xc, y, TextAnchor::Top);
+        self.set_color_for_state(context, &self.config.colors.inside, state);
+        context.rounded_rectangle(
+            text_box.x() - box_padding,
+            text_box.y() - box_padding,
+            text_box.width() + box_padding * 2.0,
+            text_box.height() + box_padding * 2.0,
+            self.config.corner_radius * scale,
+        );
+        context.fill_preserve().unwrap();
+        context.set_line_width(2.0 * scale);
+        self.set_color_for_state(&context, &self.config.colors.line, state);
+        context.stroke().unwrap();
+        self.set_color_for_state(context, &self.config.colors.text, state);
+        context.draw_text_anchored(text, xc, y, TextAnchor::Top);
+        text_box.y() + text_box.height() + box_padding
+    }
+
+    fn set_color_for_state(
+        &self,
+        context: &cairo::Context,
+        colorset: &config::ColorSet,
+        state: &DrawContext,
+    ) {
+        if self.lockout_remaining().is_some() {
+            context.set_source_color(&colorset.wrong)
+        } else if self.input_state == InputState::Clear {
             context.set_source_color(&colorset.cleared)
         } else if self.auth_state == AuthState::Validating {
             context.set_source_color(&colorset.verifying)
         } else if self.auth_state == AuthState::Invalid {
             context.set_source_color(&colorset.wrong)
         } else {
-            if self.is_caps_lock && self.config.show_caps_lock_indicator {
-                context.set_source_color(&colorset.caps_lock)
+            let color = if state.keyboard.is_caps_lock() && self.config.show_caps_lock_indicator {
+                &colorset.caps_lock
             } else {
-                context.set_source_color(&colorset.input)
-            }
+                &colorset.input
+            };
+            context.set_source_color(&hue_rotated(color, self.hue_shift()))
         };
     }
 
-    fn text_for_state(&self) -> Option<&str> {
-        if self.input_state == InputState::Clear {
-            Some("Cleared")
+    /// Degrees the hue of the idle and typing colors is currently rotated by,
+    /// slowly swinging within `indicator.hue_cycle` so no pixel stays the
+    /// same color on OLED panels
+    fn hue_shift(&self) -> f64 {
+        let cycle = &self.config.hue_cycle;
+        if cycle.degrees <= 0.0 || cycle.period_secs <= 0.0 {
+            return 0.0;
+        }
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        cycle.degrees * (secs * std::f64::consts::TAU / cycle.period_secs).sin()
+    }
+
+    fn text_for_state(&self, state: &DrawContext) -> Option<Cow<'_, str>> {
+        if self.is_initializing && self.config.show_initializing_text {
+            Some("Starting".into())
+        } else if let Some(remaining) = self.lockout_remaining() {
+            Some(format!("Retry in {}s", remaining.as_secs_f64().ceil()).into())
+        } else if !state.keyboard.is_present() && self.config.show_no_keyboard_text {
+            if state.has_pin_pad {
+                Some("Unlock with the keypad".into())
+            } else {
+                Some("Connect a keyboard to unlock".into())
+            }
+        } else if self.input_state == InputState::Clear {
+            Some("Cleared".into())
         } else if self.auth_state == AuthState::Validating {
-            Some("Verifying")
+            Some("Verifying".into())
         } else if self.auth_state == AuthState::Invalid {
-            Some("Wrong")
-        } else if self.is_caps_lock && self.config.show_caps_lock_text {
-            Some("Caps Lock")
+            self.config.show_failure_text.then(|| "Wrong".into())
+        } else if state.keyboard.is_caps_lock() && self.config.show_caps_lock_text {
+            Some("Caps Lock".into())
+        } else if let Some(message) = &self.message {
+            Some(message.as_str().into())
+        } else if self.config.show_failed_attempts
+            && state.failed_attempts > 0
+            && self.is_attempts_count_recent()
+        {
+            Some(self.failed_attempts.format().into())
         } else {
-            if self.config.show_failed_attempts && self.failed_attempts.value() > 0 {
-                Some(self.failed_attempts.format())
-            } else {
-                None
+            self.auth_prompt().map(Into::into)
+        }
+    }
+
+    /// `auth_prompt_text`, while nothing is typed
+    fn auth_prompt(&self) -> Option<&str> {
+        let prompt = self.config.auth_prompt_text.as_str();
+        (!prompt.is_empty()
+            && self.input_len == 0
+            && self.input_state == InputState::Idle
+            && self.auth_state == AuthState::Idle)
+            .then_some(prompt)
+    }
+
+    /// Color of the typing highlight for the key pressed, `None` to hide it
+    /// while verifying without `highlights.verifying`
+    fn highlight_color(&self, state: &DrawContext) -> Option<&config::Color> {
+        let highlights = &self.config.highlights;
+        let caps_lock = state.keyboard.is_caps_lock() && self.config.show_caps_lock_indicator;
+        let usual = match (self.input_state == InputState::Letter, caps_lock) {
+            (true, true) => &highlights.caps_lock_key,
+            (true, false) => &highlights.key,
+            (false, true) => &highlights.caps_lock_backspace,
+            (false, false) => &highlights.backspace,
+        };
+        match self.auth_state {
+            AuthState::Idle => Some(usual),
+            AuthState::Validating => highlights.verifying.as_ref(),
+            AuthState::Invalid => Some(highlights.wrong.as_ref().unwrap_or(usual)),
+        }
+    }
+
+    /// Radius of the typing highlight arc, the ring's unless set
+    fn highlight_radius(&self, scale: f64) -> f64 {
+        if self.config.highlight_radius < 0.0 {
+            self.config.radius * scale
+        } else {
+            self.config.highlight_radius * scale
+        }
+    }
+
+    /// Radius of a PIN pad key and the distance between key centers
+    fn pin_pad_metrics(&self, scale: f64) -> (f64, f64) {
+        let key_radius = self.config.radius * scale * 0.4;
+        (key_radius, key_radius * 2.6)
+    }
+
+    /// Keys of the PIN pad with their centers, in a grid below the ring
+    fn pin_pad_keys(&self, width: i32, height: i32, scale: f64) -> Vec<(PinKey, f64, f64)> {
+        let (xc, yc) = self.center(width, height, scale);
+        let (_, pitch) = self.pin_pad_metrics(scale);
+        let top = yc + (self.config.radius + self.config.thickness) * scale + pitch * 0.8;
+        let mut keys = Vec::new();
+        for (row, row_keys) in PinKey::LAYOUT.iter().enumerate() {
+            for (column, key) in row_keys.iter().enumerate() {
+                let x = xc + (column as f64 - 1.0) * pitch;
+                keys.push((*key, x, top + row as f64 * pitch));
             }
         }
+        keys
+    }
+
+    /// PIN pad key whose cell contains `(x, y)`
+    pub fn pin_pad_key_at(
+        &self,
+        x: f64,
+        y: f64,
+        width: i32,
+        height: i32,
+        scale: f64,
+    ) -> Option<PinKey> {
+        let (_, pitch) = self.pin_pad_metrics(scale);
+        self.pin_pad_keys(width, height, scale)
+            .into_iter()
+            .find(|(_, kx, ky)| (x - kx).abs() <= pitch / 2.0 && (y - ky).abs() <= pitch / 2.0)
+            .map(|(key, _, _)| key)
+    }
+
+    /// Draws the PIN pad in the colors of the indicator while typing
+    pub fn draw_pin_pad(&self, context: &cairo::Context, width: i32, height: i32, scale: f64) {
+        const PI: f64 = std::f64::consts::PI;
+
+        let colors = &self.config.colors;
+        let (key_radius, _) = self.pin_pad_metrics(scale);
+        for (key, x, y) in self.pin_pad_keys(width, height, scale) {
+            context.arc(x, y, key_radius, 0.0, 2.0 * PI);
+            context.set_source_color(&colors.inside.input);
+            context.fill_preserve().unwrap();
+            context.set_line_width(self.config.border_width * scale);
+            context.set_source_color(&colors.line.input);
+            context.stroke().unwrap();
+
+            let font_size = match key {
+                PinKey::Digit(_) => key_radius * 0.9,
+                PinKey::Backspace | PinKey::Enter => key_radius * 0.6,
+            };
+            configure_font_drawing(context, &self.config.font, font_size);
+            context.set_source_color(&colors.text.input);
+            context.draw_text_anchored(&key.label(), x, y, TextAnchor::Center);
+        }
+    }
+
+    /// The last typed character while it is still to be shown
+    fn revealed_char(&self) -> Option<char> {
+        self.last_char
+            .filter(|(_, at)| at.elapsed() < REVEAL_CHAR_DURATION)
+            .map(|(c, _)| c)
+    }
+
+    /// Whether the last failure is recent enough to still show the count
+    fn is_attempts_count_recent(&self) -> bool {
+        let timeout = self.config.attempts_display_timeout_secs;
+        timeout == 0
+            || self
+                .last_failure
+                .is_none_or(|at| at.elapsed() < Duration::from_secs(timeout))
+    }
+
+    /// Factor shrinking the indicator about the surface center so that the
+    /// ring, 3 radii below the center, fits surfaces too small for `radius`
+    pub fn fit_scale(&self, width: i32, height: i32) -> f64 {
+        let extent =
+            self.config.radius.max(self.highlight_radius(1.0)) + self.config.thickness / 2.0;
+        let fit_width = (width as f64 / 2.0) / extent;
+        let fit_height = (height as f64 / 2.0) / (self.config.radius * 3.0 + extent);
+        // Never NaN nor 0, which would leave cairo without an invertible matrix
+        fit_width.min(fit_height).min(1.0).clamp(0.01, 1.0)
+    }
+
+    fn center(&self, width: i32, height: i32, scale: f64) -> (f64, f64) {
+        let xc = (width as f64) * scale / 2.0;
+        let yc = (height as f64) * scale * 0.5 + self.config.radius * scale * 3.0;
+        (xc, yc)
+    }
+}
+
+impl Drawable for Indicator {
+    /// Area covered by the ring including its thickness
+    fn bounds(
+        &self,
+        _context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        _state: &DrawContext,
+    ) -> cairo::Rectangle {
+        let (xc, yc) = self.center(width, height, scale);
+        let radius = self.config.radius.max(self.highlight_radius(1.0));
+        let extent = (radius + self.config.thickness / 2.0) * scale;
+        cairo::Rectangle::new(xc - extent, yc - extent, extent * 2.0, extent * 2.0)
     }
 
-    pub fn draw(
-        &mut self,
+    fn draw(
+        &self,
         context: &cairo::Context,
         width: i32,
         height: i32,
         scale: f64,
-        keyboard: &KeyboardState,
+        state: &DrawContext,
     ) {
+        let keyboard = state.keyboard;
+        if self.is_asleep {
+            return;
+        }
         if !self.config.show_even_if_idle
             && self.auth_state == AuthState::Idle
             && self.input_state == InputState::Idle
+            && self.lockout_remaining().is_none()
+            && self
+                .visible_until
+                .is_none_or(|until| Instant::now() >= until)
+            && (!self.is_initializing || !self.config.show_initializing_text)
+            && (keyboard.is_present() || !self.config.show_no_keyboard_text)
+            && self.message.is_none()
+            && self.auth_prompt().is_none()
         {
             return;
         }
 
-        self.is_caps_lock = keyboard.is_caps_lock;
-
         let show_layout = if !self.config.hide_keyboard_layout && keyboard.get_num_layouts() > 1 {
             true
         } else {
@@ -144,138 +577,804 @@ impl Indicator {
 
         let arc_thickness = self.config.thickness * scale;
         let arc_radius = self.config.radius * scale;
-        let xc = (width as f64) * scale / 2.0;
-        let yc = (height as f64) * scale * 0.5 + arc_radius * 3.0;
+        let (xc, yc) = self.center(width, height, scale);
 
-        if self.config.font_size <= 0.0 {
-            self.config.font_size = arc_radius / 3.0;
-        }
+        let font_size = if self.config.font_size <= 0.0 {
+            arc_radius / 3.0
+        } else {
+            self.config.font_size * state.font_scale
+        };
 
         // fill inner circle
         context.set_line_width(0.0);
         context.arc(xc, yc, arc_radius, 0.0, 2.0 * PI);
-        self.set_color_for_state(&context, &self.config.colors.inside);
+        self.set_color_for_state(&context, &self.config.colors.inside, state);
         context.fill_preserve().unwrap();
         context.stroke().unwrap();
 
-        // Draw ring
+        // Draw ring, spinning with a gap while verifying
         context.set_line_width(arc_thickness);
-        context.arc(xc, yc, arc_radius, 0.0, 2.0 * PI);
-        self.set_color_for_state(&context, &self.config.colors.ring);
+        let spinner_gap = self
+            .config
+            .spinner_gap_degrees
+            .clamp(0.0, 360.0)
+            .to_radians();
+        if self.auth_state == AuthState::Validating && spinner_gap > 0.0 {
+            let turns = self.last_update.elapsed().as_secs_f64() * self.config.spinner_speed;
+            let start = turns.fract() * 2.0 * PI;
+            context.arc(xc, yc, arc_radius, start + spinner_gap, start + 2.0 * PI);
+        } else {
+            context.arc(xc, yc, arc_radius, 0.0, 2.0 * PI);
+        }
+        self.set_color_for_state(&context, &self.config.colors.ring, state);
         context.stroke().unwrap();
 
+        // One dot per typed character along the ring, clockwise from the top
+        let max_dots = self.config.max_password_dots.max(1);
+        if self.config.show_password_dots && self.input_len > 0 {
+            self.set_color_for_state(context, &self.config.colors.text, state);
+            let step = 2.0 * PI / max_dots as f64;
+            for i in 0..self.input_len.min(max_dots) {
+                let angle = -PI / 2.0 + step * i as f64;
+                let (x, y) = (xc + arc_radius * angle.cos(), yc + arc_radius * angle.sin());
+                context.arc(x, y, arc_thickness / 4.0, 0.0, 2.0 * PI);
+                context.fill().unwrap();
+            }
+        }
+
         if self.config.show_text
-            && let Some(text) = self.text_for_state()
+            && let Some(text) = self.text_for_state(state)
         {
-            configure_font_drawing(context, &self.config.font, self.config.font_size);
-            self.set_color_for_state(context, &self.config.colors.text);
-            let extents = context.text_extents(text).unwrap();
-            let font_extents = context.font_extents().unwrap();
-            let x = extents.width() / 2.0 + extents.x_bearing();
-            let y = font_extents.height() / 2.0 - font_extents.descent();
-            context.move_to(xc - x, yc + y);
-            context.show_text(text).unwrap();
-            context.close_path();
-            context.new_sub_path();
-        }
-
-        if show_layout {
-            configure_font_drawing(context, &self.config.font, self.config.font_size);
-            let text = keyboard.get_active_layout();
-            let extents = context.text_extents(text).unwrap();
-            let font_extents = context.font_extents().unwrap();
-            let box_padding = font_extents.height() * 0.2 * scale;
-            let yc = yc + arc_radius + arc_thickness + box_padding;
-            let (x_off, y_off) = (extents.x_advance() / 2.0, font_extents.height() / 2.0);
-            self.set_color_for_state(context, &self.config.colors.inside);
-            context.rectangle(
-                xc - x_off - box_padding,
-                yc,
-                x_off * 2.0 + box_padding * 2.0,
-                font_extents.height() + font_extents.descent(),
-            );
-            context.fill_preserve().unwrap();
-            context.set_line_width(2.0 * scale);
-            self.set_color_for_state(&context, &self.config.colors.line);
-            context.stroke().unwrap();
-            self.set_color_for_state(context, &self.config.colors.text);
-            context.move_to(xc - x_off, yc + box_padding * 2.0 + y_off);
-            context.show_text(text).unwrap();
-            context.close_path();
-            context.new_sub_path();
+            configure_font_drawing(context, &self.config.font, font_size);
+            self.set_color_for_state(context, &self.config.colors.text, state);
+            context.draw_text_anchored(&text, xc, yc, TextAnchor::Center);
+        }
+
+        // Label boxes stacked below the ring: the layout, then Caps Lock
+        let layout_font = self
+            .config
+            .layout_font
+            .as_ref()
+            .unwrap_or(&self.config.font);
+        let layout_font_size = if self.config.layout_font_size <= 0.0 {
+            font_size
+        } else {
+            self.config.layout_font_size * state.font_scale
+        };
+        let mut label_top = yc + arc_radius + arc_thickness;
+        if show_layout && let Some(text) = keyboard.get_active_layout() {
+            let names = state.layout_names;
+            let text = names
+                .get(text)
+                .or_else(|| names.get(&keyboard.get_active_layout_index().to_string()))
+                .map_or(text, String::as_str);
+            configure_font_drawing(context, layout_font, layout_font_size);
+            label_top = self.draw_label_box(context, text, xc, label_top, scale, state);
+        }
+        if self.config.caps_lock_box && keyboard.is_caps_lock() {
+            configure_font_drawing(context, layout_font, layout_font_size);
+            self.draw_label_box(context, "Caps Lock", xc, label_top, scale, state);
         }
 
-        if self.input_state == InputState::Letter || self.input_state == InputState::Backspace {
+        let show_feedback = state.is_focused || self.config.feedback_on == config::FeedbackOn::All;
+        if show_feedback
+            && (self.input_state == InputState::Letter || self.input_state == InputState::Backspace)
+            && let Some(highlight) = self.highlight_color(state)
+        {
             let highlight_start = self.highlight_start as f64 * (PI / 1024.0);
             let highlight_end = highlight_start + TYPE_INDICATOR_RANGE;
-            context.arc(xc, yc, arc_radius, highlight_start, highlight_end);
-            let highlight = if self.input_state == InputState::Letter {
-                if self.is_caps_lock && self.config.show_caps_lock_indicator {
-                    &self.config.highlights.caps_lock_key
-                } else {
-                    &self.config.highlights.key
-                }
-            } else {
-                if self.is_caps_lock && self.config.show_caps_lock_indicator {
-                    &self.config.highlights.caps_lock_backspace
-                } else {
-                    &self.config.highlights.backspace
-                }
-            };
+            let highlight_radius = self.highlight_radius(scale);
+            context.arc(xc, yc, highlight_radius, highlight_start, highlight_end);
             context.set_source_color(highlight);
             context.stroke().unwrap();
+
+            // Flash the typed character just outside the highlight
+            if self.input_state == InputState::Letter
+                && let Some(c) = self.revealed_char()
+            {
+                let angle = highlight_start + TYPE_INDICATOR_RANGE / 2.0;
+                let distance = highlight_radius + arc_thickness / 2.0 + font_size * 0.75;
+                configure_font_drawing(context, &self.config.font, font_size);
+                context.draw_text_anchored(
+                    c.encode_utf8(&mut [0; 4]),
+                    xc + distance * angle.cos(),
+                    yc + distance * angle.sin(),
+                    TextAnchor::Center,
+                );
+            }
         }
 
         // Draw inner + outer border of the circle
-        self.set_color_for_state(&context, &self.config.colors.line);
-        context.set_line_width(2.0 * scale);
-        context.arc(xc, yc, arc_radius - arc_thickness / 2.0, 0.0, 2.0 * PI);
-        context.stroke().unwrap();
-        context.arc(xc, yc, arc_radius + arc_thickness / 2.0, 0.0, 2.0 * PI);
-        context.stroke().unwrap();
+        if self.config.show_borders {
+            self.set_color_for_state(&context, &self.config.colors.line, state);
+            context.set_line_width(self.config.border_width * scale);
+            context.arc(xc, yc, arc_radius - arc_thickness / 2.0, 0.0, 2.0 * PI);
+            context.stroke().unwrap();
+            context.arc(xc, yc, arc_radius + arc_thickness / 2.0, 0.0, 2.0 * PI);
+            context.stroke().unwrap();
+        }
     }
 }
 
+/// Zone the clock shows the time in
+#[derive(Clone, Copy)]
+enum ClockZone {
+    /// Offset reported by the system, which may be unavailable
+    Local,
+    /// Configured zone, the offset is looked up on every render to follow DST
+    Named(&'static Tz),
+    /// Configured zone could not be resolved
+    Utc,
+}
+
+/// `color` with its hue rotated by `degrees`, keeping its luminance, as
+/// done by the SVG `hueRotate` filter
+fn hue_rotated(color: &config::Color, degrees: f64) -> config::Color {
+    if degrees == 0.0 {
+        return color.clone();
+    }
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let (r, g, b) = (color.red, color.green, color.blue);
+    let channel =
+        |weights: [f64; 3]| (weights[0] * r + weights[1] * g + weights[2] * b).clamp(0.0, 1.0);
+    config::Color {
+        red: channel([
+            0.213 + cos * 0.787 - sin * 0.213,
+            0.715 - cos * 0.715 - sin * 0.715,
+            0.072 - cos * 0.072 + sin * 0.928,
+        ]),
+        green: channel([
+            0.213 - cos * 0.213 + sin * 0.143,
+            0.715 + cos * 0.285 + sin * 0.140,
+            0.072 - cos * 0.072 - sin * 0.283,
+        ]),
+        blue: channel([
+            0.213 - cos * 0.213 - sin * 0.787,
+            0.715 - cos * 0.715 + sin * 0.715,
+            0.072 + cos * 0.928 + sin * 0.072,
+        ]),
+        alpha: color.alpha,
+    }
+}
+
+/// Format of the clock time, e.g. "09:05" or "9:05:30 PM", or `clock.format`
+/// if it is valid
+fn time_format(config: &config::Clock) -> time::format_description::OwnedFormatItem {
+    if !config.format.is_empty() {
+        match time::format_description::parse_owned::<2>(&config.format) {
+            Ok(format) => return format,
+            Err(err) => error!(
+                "Invalid clock.format {:?} with error {err}, using the default format",
+                config.format
+            ),
+        }
+    }
+    let padding = if config.pad_hours { "zero" } else { "none" };
+    let repr = if config.use_12h { "12" } else { "24" };
+    let mut format = format!("[hour padding:{padding} repr:{repr}]:[minute]");
+    if config.show_seconds {
+        format.push_str(":[second]");
+    }
+    if config.use_12h {
+        format.push_str(" [period]");
+    }
+    time::format_description::parse_owned::<2>(&format).unwrap()
+}
+
 pub struct Clock {
     pub config: config::Clock,
+    /// Time format built from the config once, rather than on every frame
+    format: time::format_description::OwnedFormatItem,
+    zone: ClockZone,
+    /// Format of the date line below the time, with `show_date`
+    date_format: Option<time::format_description::OwnedFormatItem>,
+    /// Secondary clocks from `clock.extra_zones` with their labels
+    extra_zones: Vec<(String, ClockZone)>,
 }
 
 impl Clock {
-    pub fn draw(&self, context: &cairo::Context, width: i32, height: i32, scale: f64) {
+    pub fn new(config: config::Clock) -> Self {
+        let zone = match config.timezone.as_deref() {
+            None if config.use_utc => ClockZone::Utc,
+            None => ClockZone::Local,
+            Some(name) => match time_tz::timezones::get_by_name(name) {
+                Some(tz) => ClockZone::Named(tz),
+                None => {
+                    error!("Unknown clock timezone '{name}', falling back to UTC");
+                    ClockZone::Utc
+                }
+            },
+        };
+        let extra_zones = config
+            .extra_zones
+            .iter()
+            .filter_map(|name| match time_tz::timezones::get_by_name(name) {
+                Some(tz) => Some((zone_label(name), ClockZone::Named(tz))),
+                None => {
+                    error!("Unknown extra clock timezone '{name}', skipping it");
+                    None
+                }
+            })
+            .collect();
+        let date_format = config.show_date.then(|| {
+            time::format_description::parse_owned::<2>(&config.date_format)
+                .inspect_err(|err| {
+                    error!(
+                        "Invalid clock.date_format {:?} with error {err}, hiding the date",
+                        config.date_format
+                    )
+                })
+                .ok()
+        });
+        Self {
+            format: time_format(&config),
+            config,
+            zone,
+            date_format: date_format.flatten(),
+            extra_zones,
+        }
+    }
+
+    fn text(&self, zone: ClockZone) -> String {
+        self.format_now(zone, &self.format)
+    }
+
+    /// Time until the displayed text next changes, on the next second or
+    /// minute. Zone offsets are whole minutes, so UTC boundaries are local ones
+    pub fn until_next_tick(&self) -> Duration {
+        let shows_seconds = if self.config.format.is_empty() {
+            self.config.show_seconds
+        } else {
+            self.config.format.contains("second")
+        };
+        let period = if shows_seconds { 1_000 } else { 60_000 };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        Duration::from_millis(period - now % period)
+    }
+
+    fn format_now(
+        &self,
+        zone: ClockZone,
+        format: &time::format_description::OwnedFormatItem,
+    ) -> String {
         use time::OffsetDateTime;
-        use time::format_description;
 
+        let now = match zone {
+            ClockZone::Local => OffsetDateTime::now_local(),
+            ClockZone::Named(tz) => Ok(OffsetDateTime::now_utc().to_timezone(tz)),
+            ClockZone::Utc => Ok(OffsetDateTime::now_utc()),
+        };
+        match now {
+            Ok(dt) => dt.format(format).unwrap(),
+            _ => self.config.fallback_text.clone(),
+        }
+    }
+
+    /// Calls `f` with the font configured for every line of the clock: the
+    /// main time at `clock.x` and `clock.y`, then the date and the extra
+    /// zones stacked below
+    fn for_each_line(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        font_scale: f64,
+        f: impl FnMut(&str, f64, f64, TextAnchor),
+    ) {
+        let (xc, yc) = self.origin(context, width, height, scale, font_scale);
+        self.for_each_line_at(context, xc, yc, scale, font_scale, f);
+    }
+
+    /// Center of the main time line, moving the lines anchored to an edge
+    /// so they all fit within `CLOCK_EDGE_MARGIN` of it
+    fn origin(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        font_scale: f64,
+    ) -> (f64, f64) {
+        let (x, y) = (self.config.x, self.config.y);
+        let anchored = |position| matches!(position, ClockPosition::Start | ClockPosition::End);
+        // Extent of the lines around the origin, only needed against an edge
+        let mut block: Option<(f64, f64, f64, f64)> = None;
+        if anchored(x) || anchored(y) {
+            context.save().unwrap();
+            self.for_each_line_at(
+                context,
+                0.0,
+                0.0,
+                scale,
+                font_scale,
+                |text, x, y, anchor| {
+                    let line = context.text_bounds_anchored(text, x, y, anchor);
+                    let (x0, y0) = (line.x(), line.y());
+                    let (x1, y1) = (x0 + line.width(), y0 + line.height());
+                    block = Some(match block {
+                        None => (x0, y0, x1, y1),
+                        Some(b) => (b.0.min(x0), b.1.min(y0), b.2.max(x1), b.3.max(y1)),
+                    });
+                },
+            );
+            context.restore().unwrap();
+        }
+        let (x0, y0, x1, y1) = block.unwrap_or_default();
+        let margin = CLOCK_EDGE_MARGIN * scale;
+        let along = |position, size: i32, start: f64, end: f64| {
+            let size = size as f64 * scale;
+            match position {
+                ClockPosition::Start => margin - start,
+                ClockPosition::Center => size / 2.0,
+                ClockPosition::End => size - margin - end,
+                ClockPosition::Offset(offset) => size / 2.0 + offset * scale,
+            }
+        };
+        (along(x, width, x0, x1), along(y, height, y0, y1))
+    }
+
+    fn for_each_line_at(
+        &self,
+        context: &cairo::Context,
+        xc: f64,
+        yc: f64,
+        scale: f64,
+        font_scale: f64,
+        mut f: impl FnMut(&str, f64, f64, TextAnchor),
+    ) {
+        let text = self.text(self.zone);
+        configure_font_drawing(
+            context,
+            &self.config.font,
+            self.config.font_size * font_scale,
+        );
+        f(&text, xc, yc, TextAnchor::Center);
+
+        let gap = self.config.extra_zones_gap * scale;
+        let mut y = context
+            .text_bounds_anchored(&text, xc, yc, TextAnchor::Center)
+            .y()
+            + context.font_extents().unwrap().height();
+        if let Some(format) = &self.date_format {
+            let text = self.format_now(self.zone, format);
+            configure_font_drawing(
+                context,
+                &self.config.font,
+                self.config.date_font_size * font_scale,
+            );
+            y += gap;
+            f(&text, xc, y, TextAnchor::Top);
+            y += context.font_extents().unwrap().height();
+        }
+        for (label, zone) in &self.extra_zones {
+            let text = format!("{label} {}", self.text(*zone));
+            configure_font_drawing(
+                context,
+                &self.config.font,
+                self.config.extra_zones_font_size * font_scale,
+            );
+            y += gap;
+            f(&text, xc, y, TextAnchor::Top);
+            y += context.font_extents().unwrap().height();
+        }
+    }
+}
+
+impl Drawable for Clock {
+    /// Area covered by the clock text including its outline
+    fn bounds(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    ) -> cairo::Rectangle {
+        let mut text_box: Option<cairo::Rectangle> = None;
+        context.save().unwrap();
+        let font_scale = state.font_scale;
+        self.for_each_line(
+            context,
+            width,
+            height,
+            scale,
+            font_scale,
+            |text, x, y, anchor| {
+                let line = context.text_bounds_anchored(text, x, y, anchor);
+                text_box = Some(match text_box {
+                    None => line,
+                    Some(b) => {
+                        let (x0, y0) = (b.x().min(line.x()), b.y().min(line.y()));
+                        let x1 = (b.x() + b.width()).max(line.x() + line.width());
+                        let y1 = (b.y() + b.height()).max(line.y() + line.height());
+                        cairo::Rectangle::new(x0, y0, x1 - x0, y1 - y0)
+                    }
+                });
+            },
+        );
+        context.restore().unwrap();
+        let text_box = text_box.unwrap();
+
+        let outline = self.config.outline_width / 2.0;
+        cairo::Rectangle::new(
+            text_box.x() - outline,
+            text_box.y() - outline,
+            text_box.width() + outline * 2.0,
+            text_box.height() + outline * 2.0,
+        )
+    }
+
+    fn draw(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    ) {
+        let text_color = if self.config.caps_lock_tint && state.keyboard.is_caps_lock() {
+            &self.config.caps_lock_color
+        } else {
+            &self.config.text_color
+        };
+        let font_scale = state.font_scale;
+        self.for_each_line(
+            context,
+            width,
+            height,
+            scale,
+            font_scale,
+            |text, x, y, anchor| {
+                context.text_path_anchored(text, x, y, anchor);
+
+                context.set_source_color(text_color);
+                context.fill_preserve().unwrap();
+
+                context.set_source_color(&self.config.outline_color);
+                context.set_line_width(self.config.outline_width);
+                context.stroke().unwrap();
+
+                context.close_path();
+                context.new_sub_path();
+            },
+        );
+    }
+}
+
+/// Short label for an IANA zone name, e.g. "New York" for "America/New_York"
+fn zone_label(name: &str) -> String {
+    name.rsplit('/').next().unwrap_or(name).replace('_', " ")
+}
+
+/// Picture of the user, centered above the clock
+pub struct Avatar {
+    pub config: config::Avatar,
+    /// None if no picture was found, hiding the avatar
+    pub image: Option<cairo::ImageSurface>,
+}
+
+impl Drawable for Avatar {
+    fn bounds(
+        &self,
+        _context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        _state: &DrawContext,
+    ) -> cairo::Rectangle {
+        let size = self.config.size * scale;
         let xc = (width as f64) * scale / 2.0;
-        let yc = (height as f64) * scale / 2.0;
+        let yc = (height as f64) * scale / 2.0 - size * 1.5;
+        cairo::Rectangle::new(xc - size / 2.0, yc - size / 2.0, size, size)
+    }
 
-        let format = if self.config.show_seconds {
-            format_description::parse_borrowed::<2>("[hour]:[minute]:[second]")
+    fn draw(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    ) {
+        let Some(image) = self.image.as_ref() else {
+            return;
+        };
+        let area = self.bounds(context, width, height, scale, state);
+        context.save().unwrap();
+        if self.config.circular {
+            let radius = area.width() / 2.0;
+            context.arc(
+                area.x() + radius,
+                area.y() + radius,
+                radius,
+                0.0,
+                2.0 * std::f64::consts::PI,
+            );
         } else {
-            format_description::parse_borrowed::<2>("[hour]:[minute]")
+            context.rectangle(area.x(), area.y(), area.width(), area.height());
+        }
+        context.clip();
+
+        // Cover the area, cropping the longer side of the picture
+        let (image_width, image_height) = (image.width() as f64, image.height() as f64);
+        let image_scale = area.width() / image_width.min(image_height);
+        context.translate(
+            area.x() + (area.width() - image_width * image_scale) / 2.0,
+            area.y() + (area.height() - image_height * image_scale) / 2.0,
+        );
+        context.scale(image_scale, image_scale);
+        context.set_source_surface(image, 0.0, 0.0).unwrap();
+        context.source().set_filter(cairo::Filter::Good);
+        context.paint().unwrap();
+        context.restore().unwrap();
+    }
+}
+
+/// Badges naming the active modifiers, in a row near the bottom of the
+/// surface
+pub struct ModifierBadges {
+    pub config: config::Modifiers,
+}
+
+impl ModifierBadges {
+    /// Label and box of a badge for each of `modifiers`, laid out as a
+    /// centered row. Leaves the badge font configured
+    fn badges(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        font_scale: f64,
+        modifiers: &[config::Modifier],
+    ) -> Vec<(&'static str, cairo::Rectangle)> {
+        let font_size = self.config.font_size * font_scale * scale;
+        configure_font_drawing(context, &self.config.font, font_size);
+        let padding = font_size * 0.4;
+        let labels = modifiers
+            .iter()
+            .map(|modifier| modifier_label(*modifier))
+            .collect::<Vec<_>>();
+        let text_boxes = labels
+            .iter()
+            .map(|label| context.text_bounds_anchored(label, 0.0, 0.0, TextAnchor::TopLeft))
+            .collect::<Vec<_>>();
+        let row_width = text_boxes
+            .iter()
+            .map(|text_box| text_box.width() + padding * 2.0)
+            .sum::<f64>()
+            + padding * labels.len().saturating_sub(1) as f64;
+        let badge_height = text_boxes.first().map_or(0.0, |b| b.height()) + padding * 2.0;
+
+        let mut x = ((width as f64) * scale - row_width) / 2.0;
+        let y = (height as f64) * scale - badge_height - font_size * 2.0;
+        labels
+            .into_iter()
+            .zip(text_boxes)
+            .map(|(label, text_box)| {
+                let badge_width = text_box.width() + padding * 2.0;
+                let badge = cairo::Rectangle::new(x, y, badge_width, badge_height);
+                x += badge_width + padding;
+                (label, badge)
+            })
+            .collect()
+    }
+}
+
+fn modifier_label(modifier: config::Modifier) -> &'static str {
+    match modifier {
+        config::Modifier::Shift => "Shift",
+        config::Modifier::Ctrl => "Ctrl",
+        config::Modifier::Alt => "Alt",
+        config::Modifier::Super => "Super",
+        config::Modifier::CapsLock => "Caps Lock",
+        config::Modifier::NumLock => "Num Lock",
+    }
+}
+
+impl Drawable for ModifierBadges {
+    /// Area of the badges of all tracked modifiers, so the layout doesn't
+    /// move as modifiers are pressed
+    fn bounds(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    ) -> cairo::Rectangle {
+        context.save().unwrap();
+        let badges = self.badges(
+            context,
+            width,
+            height,
+            scale,
+            state.font_scale,
+            &self.config.tracked,
+        );
+        context.restore().unwrap();
+        match (badges.first(), badges.last()) {
+            (Some((_, first)), Some((_, last))) => cairo::Rectangle::new(
+                first.x(),
+                first.y(),
+                last.x() + last.width() - first.x(),
+                first.height(),
+            ),
+            _ => cairo::Rectangle::new(
+                (width as f64) * scale / 2.0,
+                (height as f64) * scale,
+                0.0,
+                0.0,
+            ),
+        }
+    }
+
+    fn draw(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        scale: f64,
+        state: &DrawContext,
+    ) {
+        let active = self
+            .config
+            .tracked
+            .iter()
+            .copied()
+            .filter(|modifier| state.keyboard.is_modifier_active(*modifier))
+            .collect::<Vec<_>>();
+        let badges = self.badges(context, width, height, scale, state.font_scale, &active);
+        for (label, badge) in badges {
+            context.rounded_rectangle(
+                badge.x(),
+                badge.y(),
+                badge.width(),
+                badge.height(),
+                badge.height() / 2.0,
+            );
+            context.set_source_color(&self.config.badge_color);
+            context.fill().unwrap();
+
+            context.set_source_color(&self.config.text_color);
+            let (xc, yc) = (
+                badge.x() + badge.width() / 2.0,
+                badge.y() + badge.height() / 2.0,
+            );
+            context.draw_text_anchored(label, xc, yc, TextAnchor::Center);
         }
-        .unwrap();
-        let text = match OffsetDateTime::now_local() {
-            Ok(dt) => dt.format(&format).unwrap(),
-            _ => "Unknown time".to_string(),
+    }
+}
+
+/// A rounded panel drawn behind the other overlay elements
+pub struct OverlayCard {
+    pub config: config::OverlayCard,
+}
+
+impl OverlayCard {
+    /// Draws the card covering the union of `bounds` plus padding
+    pub fn draw(&self, context: &cairo::Context, bounds: &[cairo::Rectangle], scale: f64) {
+        let Some(first) = bounds.first() else {
+            return;
         };
+        let (mut x0, mut y0) = (first.x(), first.y());
+        let (mut x1, mut y1) = (first.x() + first.width(), first.y() + first.height());
+        for rect in &bounds[1..] {
+            x0 = x0.min(rect.x());
+            y0 = y0.min(rect.y());
+            x1 = x1.max(rect.x() + rect.width());
+            y1 = y1.max(rect.y() + rect.height());
+        }
 
-        configure_font_drawing(context, &self.config.font, self.config.font_size);
+        let padding = self.config.padding * scale;
+        context.save().unwrap();
+        context.rounded_rectangle(
+            x0 - padding,
+            y0 - padding,
+            x1 - x0 + padding * 2.0,
+            y1 - y0 + padding * 2.0,
+            self.config.corner_radius * scale,
+        );
+        context.set_source_color(&self.config.color);
+        context.fill().unwrap();
+        context.restore().unwrap();
+    }
+}
 
-        let extents = context.text_extents(&text).unwrap();
-        let font_extents = context.font_extents().unwrap();
-        let x = extents.x_advance() / 2.0;
-        let y = font_extents.height() / 2.0 - font_extents.descent();
-        context.move_to(xc - x, yc + y);
-        context.text_path(&text);
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
 
-        context.set_source_color(&self.config.text_color);
-        context.fill_preserve().unwrap();
+    use super::*;
 
-        context.set_source_color(&self.config.outline_color);
-        context.set_line_width(self.config.outline_width);
-        context.stroke().unwrap();
+    fn default_indicator() -> Indicator {
+        let table = config::Config::merge_config_with_defaults(toml::Table::new());
+        let config = config::Config::deserialize(table).unwrap();
+        let mut indicator = Indicator::new(config.indicator);
+        indicator.config.show_no_keyboard_text = false;
+        indicator
+    }
+
+    #[test]
+    fn attempts_counter_counts_up_to_a_cap() {
+        let mut counter = AttemptsCounter::new();
+        assert_eq!((counter.value(), counter.format()), (0, ""));
+
+        counter.inc();
+        counter.inc();
+        assert_eq!((counter.value(), counter.format()), (2, "2"));
+
+        counter.set(999);
+        counter.inc();
+        counter.inc();
+        assert_eq!((counter.value(), counter.format()), (1000, "999+"));
+    }
+
+    #[test]
+    fn attempts_counter_reset_clears_the_text() {
+        let mut counter = AttemptsCounter::new();
+        counter.set(5);
+
+        counter.reset();
+
+        assert_eq!((counter.value(), counter.format()), (0, ""));
+    }
 
-        context.close_path();
-        context.new_sub_path();
+    #[test]
+    fn inner_circle_alpha_matches_config() {
+        for alpha in [0x40, 0x80, 0xC0, 0xFF] {
+            let mut indicator = default_indicator();
+            indicator.config.colors.inside.input =
+                toml::Value::String(format!("000000{alpha:02X}"))
+                    .try_into()
+                    .unwrap();
+            indicator.input_state = InputState::Letter;
+
+            let (width, height) = (200, 400);
+            let mut surface =
+                cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+            {
+                let context = cairo::Context::new(&surface).unwrap();
+                let keyboard = KeyboardState::new(None);
+                let state = DrawContext {
+                    keyboard: &keyboard,
+                    failed_attempts: 0,
+                    is_focused: true,
+                    has_pin_pad: false,
+                    layout_names: &HashMap::new(),
+                    font_scale: 1.0,
+                };
+                indicator.draw(&context, width, height, 1.0, &state);
+            }
+
+            let (xc, yc) = indicator.center(width, height, 1.0);
+            let stride = surface.stride() as usize;
+            let data = surface.data().unwrap();
+            // ARGB32 is stored native-endian, alpha in the high byte
+            let pixel = &data[yc as usize * stride + xc as usize * 4..][..4];
+            let pixel = u32::from_ne_bytes(pixel.try_into().unwrap());
+            assert_eq!(pixel >> 24, alpha, "inside alpha {alpha:#04X}");
+        }
+    }
+
+    #[test]
+    fn pin_pad_keys_are_hit_within_their_cells() {
+        let indicator = default_indicator();
+        let (width, height) = (600, 1000);
+        let keys = indicator.pin_pad_keys(width, height, 1.0);
+        assert_eq!(keys.len(), 12);
+
+        for (key, x, y) in keys {
+            assert_eq!(
+                indicator.pin_pad_key_at(x + 10.0, y - 10.0, width, height, 1.0),
+                Some(key)
+            );
+        }
+        let (xc, yc) = indicator.center(width, height, 1.0);
+        assert_eq!(indicator.pin_pad_key_at(xc, yc, width, height, 1.0), None);
     }
 }
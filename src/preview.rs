@@ -0,0 +1,82 @@
+//! Insecure preview mode for theming without locking the session.
+//!
+//! Surfaces are created with wlr-layer-shell instead of ext-session-lock and
+//! passwords are checked against [`PREVIEW_PASSWORD`] instead of PAM. Nothing
+//! is ever locked, "unlocking" simply closes the preview.
+
+use log::{debug, warn};
+use smithay_client_toolkit::{
+    delegate_layer,
+    shell::{
+        WaylandSurface,
+        wlr_layer::{
+            Anchor, KeyboardInteractivity, Layer, LayerShell, LayerShellHandler, LayerSurface,
+            LayerSurfaceConfigure,
+        },
+    },
+};
+use wayland_client::{Connection, Proxy, QueueHandle, globals::GlobalList, protocol::wl_output};
+
+use crate::{LifeCycle, State, SurfaceRole, auth::PREVIEW_PASSWORD, config::Config};
+
+pub fn bind_layer_shell(
+    config: &Config,
+    globals: &GlobalList,
+    qh: &QueueHandle<State>,
+) -> Option<LayerShell> {
+    if !config.preview {
+        return None;
+    }
+    warn!(
+        "Running in INSECURE preview mode: the session is not locked. Type '{PREVIEW_PASSWORD}' to close the preview."
+    );
+    Some(LayerShell::bind(globals, qh).expect("zwlr_layer_shell_v1 not available"))
+}
+
+impl State {
+    pub fn create_preview_surface(&mut self, qh: &QueueHandle<Self>, output: wl_output::WlOutput) {
+        if self.output_to_lock_surfaces.contains_key(&output.id()) {
+            debug!("Output {} already has a preview surface", output.id());
+            return;
+        }
+        let Some(layer_shell) = self.layer_shell.as_ref() else {
+            return;
+        };
+
+        let surface = self.compositor_state.create_surface(qh);
+        let layer = layer_shell.create_layer_surface(
+            qh,
+            surface.clone(),
+            Layer::Overlay,
+            Some("waylockrs-preview"),
+            Some(&output),
+        );
+        layer.set_anchor(Anchor::all());
+        layer.set_exclusive_zone(-1);
+        layer.set_keyboard_interactivity(KeyboardInteractivity::Exclusive);
+        layer.commit();
+
+        self.insert_lock_surface(qh, &output, surface, SurfaceRole::Preview(layer));
+    }
+}
+
+impl LayerShellHandler for State {
+    fn closed(&mut self, _conn: &Connection, _qh: &QueueHandle<Self>, _layer: &LayerSurface) {
+        self.lock_surfaces.clear();
+        self.lifecycle = LifeCycle::Authenticated;
+    }
+
+    fn configure(
+        &mut self,
+        conn: &Connection,
+        qh: &QueueHandle<Self>,
+        layer: &LayerSurface,
+        configure: LayerSurfaceConfigure,
+        _serial: u32,
+    ) {
+        let (width, height) = configure.new_size;
+        self.configure_lock_surface(conn, qh, layer.wl_surface().id(), width, height);
+    }
+}
+
+delegate_layer!(State);
@@ -0,0 +1,68 @@
+//! Offline rendering of the lock screen to a PNG so themes and layouts can be
+//! checked at a given resolution without a Wayland session
+
+use crate::avatar::load_avatar;
+use crate::background_image::load_configured_image;
+use crate::config::{Config, RenderState};
+use crate::keyboard_state::KeyboardState;
+use crate::overlay::{self, Avatar, Clock, Indicator, ModifierBadges, OverlayCard};
+use crate::scene::Scene;
+
+pub fn render_to_png(config: &Config, path: &str) -> Result<(), String> {
+    // Lacking a keyboard offline is no reason to fall back to the PIN pad
+    let config = &Config {
+        pin_pad_fallback: false,
+        ..config.clone()
+    };
+    let (width, height) = (config.width as i32, config.height as i32);
+    let background_image = load_configured_image(config);
+
+    let mut indicator = Indicator::new(config.indicator.clone());
+    // There is no seat offline, so don't ask for a keyboard
+    indicator.config.show_no_keyboard_text = false;
+    match config.render_state {
+        RenderState::Idle => {}
+        RenderState::Typing => {
+            indicator.input_state = overlay::InputState::Letter;
+            // A few characters for `show_password_dots`
+            indicator.input_len = 6;
+        }
+        RenderState::Verifying => indicator.auth_state = overlay::AuthState::Validating,
+        RenderState::Wrong => indicator.auth_state = overlay::AuthState::Invalid,
+        RenderState::Cleared => indicator.input_state = overlay::InputState::Clear,
+    }
+
+    let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, width, height)
+        .map_err(|err| format!("Failed to create a {width}x{height} surface: {err}"))?;
+    {
+        let context = cairo::Context::new(&surface).map_err(|err| err.to_string())?;
+        let scene = Scene {
+            config,
+            background_image: background_image.as_ref(),
+            background_mode: config.background_mode,
+            crossfade_from: None,
+            indicator: &indicator,
+            clock: &Clock::new(config.clock.clone()),
+            overlay_card: &OverlayCard {
+                config: config.overlay_card.clone(),
+            },
+            modifier_badges: &ModifierBadges {
+                config: config.modifiers.clone(),
+            },
+            avatar: &Avatar {
+                config: config.avatar.clone(),
+                image: config.show_avatar.then(|| load_avatar(config)).flatten(),
+            },
+            keyboard: &KeyboardState::new(None),
+            // Shows the PIN pad if enabled, as on a touchscreen
+            has_touch: true,
+        };
+        scene.draw_background(&context, width, height, true);
+        scene.draw_overlays(&context, width, height, true, true, 1);
+    }
+
+    let mut file = std::fs::File::create(path).map_err(|err| err.to_string())?;
+    surface
+        .write_to_png(&mut file)
+        .map_err(|err| err.to_string())
+}
@@ -0,0 +1,562 @@
+//! Content of a lock surface, drawn independently of any Wayland objects so
+//! it can also be rendered offline
+
+use std::f64::consts::TAU;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::background_image::render_background_image;
+use crate::blur;
+use crate::cairo_extras::CairoExtras;
+use crate::config::{BackgroundMode, Config, FontScaling, Layout, OverlayElement, ShowOn};
+use crate::keyboard_state::KeyboardState;
+use crate::overlay::{
+    AuthState, Avatar, Clock, DrawContext, Drawable, Indicator, InputState, ModifierBadges,
+    OverlayCard, PinKey,
+};
+
+pub struct Scene<'a> {
+    pub config: &'a Config,
+    pub background_image: Option<&'a cairo::ImageSurface>,
+    pub background_mode: BackgroundMode,
+    /// Image the background is fading in from, and the fade progress (0..1)
+    pub crossfade_from: Option<(&'a cairo::ImageSurface, f64)>,
+    pub indicator: &'a Indicator,
+    pub clock: &'a Clock,
+    pub overlay_card: &'a OverlayCard,
+    pub modifier_badges: &'a ModifierBadges,
+    pub avatar: &'a Avatar,
+    pub keyboard: &'a KeyboardState,
+    /// Whether the seat has a touchscreen, for `show_pin_pad` and
+    /// `pin_pad_fallback`
+    pub has_touch: bool,
+}
+
+impl Scene<'_> {
+    /// Paints the background color and image, crossfading from the previous
+    /// image if one is set, then dims it by `dim`, blurs it behind the clock
+    /// and frames it by `border`
+    pub fn draw_background(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        is_primary: bool,
+    ) {
+        context.set_antialias(cairo::Antialias::Best);
+        match self.crossfade_from {
+            Some((previous, progress)) => {
+                self.draw_background_image(context, Some(previous), width, height);
+                context.push_group();
+                self.draw_background_image(context, self.background_image, width, height);
+                context.pop_group_to_source().unwrap();
+                context.paint_with_alpha(progress).unwrap();
+            }
+            None => self.draw_background_image(context, self.background_image, width, height),
+        }
+
+        let dim = self.config.dim.clamp(0.0, 1.0);
+        if dim > 0.0 {
+            context.set_source_color(&self.config.dim_color);
+            context.paint_with_alpha(dim).unwrap();
+        }
+
+        if self.config.clock.backdrop_blur > 0.0 {
+            self.blur_clock_backdrop(context, width, height, is_primary);
+        }
+
+        let border = self.config.border.width.min(width.min(height) as f64 / 2.0);
+        if border > 0.0 {
+            context.rectangle(
+                border / 2.0,
+                border / 2.0,
+                width as f64 - border,
+                height as f64 - border,
+            );
+            context.set_source_color(&self.config.border.color);
+            context.set_line_width(border);
+            context.stroke().unwrap();
+        }
+    }
+
+    /// Blurs the background behind the clock, found as `draw_overlays` places
+    /// it. Done with the background so it isn't redone on every frame, the
+    /// padding covering the clock text changing width
+    fn blur_clock_backdrop(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        is_primary: bool,
+    ) {
+        let elements = self.elements(is_primary);
+        let Some(index) = elements
+            .iter()
+            .position(|(kind, _)| *kind == OverlayElement::Clock)
+        else {
+            return;
+        };
+        let surface = context.target();
+        let (scale, _) = surface.device_scale();
+        let state = self.draw_context(true, scale as i32);
+        let offsets = self.layout_offsets(context, &elements, width, height, &state);
+        let (kind, element) = elements[index];
+        let b = self.element_bounds(context, kind, element, width, height, &state);
+        let (jitter_x, jitter_y) = self.jitter_offset();
+        let radius = self.config.clock.backdrop_blur;
+        let padding = radius + self.config.clock.font_size * 0.25;
+        let x0 = (b.x() + jitter_x - padding).floor().max(0.0);
+        let y0 = (b.y() + offsets[index] + jitter_y - padding)
+            .floor()
+            .max(0.0);
+        let x1 = (b.x() + b.width() + jitter_x + padding)
+            .ceil()
+            .min(width as f64);
+        let y1 = (b.y() + b.height() + offsets[index] + jitter_y + padding)
+            .ceil()
+            .min(height as f64);
+        if x1 <= x0 || y1 <= y0 {
+            return;
+        }
+
+        let Ok(mut region) = cairo::ImageSurface::create(
+            cairo::Format::ARgb32,
+            ((x1 - x0) * scale) as i32,
+            ((y1 - y0) * scale) as i32,
+        ) else {
+            return;
+        };
+        region.set_device_scale(scale, scale);
+        {
+            let region_context = cairo::Context::new(&region).unwrap();
+            region_context
+                .set_source_surface(&surface, -x0, -y0)
+                .unwrap();
+            region_context.paint().unwrap();
+        }
+        blur::blur(&mut region, (radius * scale).round() as usize);
+        context.save().unwrap();
+        context.set_source_surface(&region, x0, y0).unwrap();
+        context.paint().unwrap();
+        context.restore().unwrap();
+    }
+
+    fn draw_background_image(
+        &self,
+        context: &cairo::Context,
+        image: Option<&cairo::ImageSurface>,
+        width: i32,
+        height: i32,
+    ) {
+        context.save().unwrap();
+
+        context.set_operator(cairo::Operator::Source);
+        context.set_source_color(&self.config.background_color);
+        context.paint().unwrap();
+        context.save().unwrap();
+
+        context.set_operator(cairo::Operator::Over);
+        if let Some(image) = image {
+            render_background_image(
+                context,
+                image,
+                self.background_mode,
+                self.config.background_filter,
+                width,
+                height,
+            );
+        }
+        context.restore().unwrap();
+        context.identity_matrix();
+        context.restore().unwrap();
+    }
+
+    /// Enabled elements of `overlay.order` for a primary or secondary
+    /// output, bottom first
+    fn elements(&self, is_primary: bool) -> Vec<(OverlayElement, &dyn Drawable)> {
+        let (show_clock, show_indicator) = match self.config.show_on {
+            ShowOn::All => (true, true),
+            ShowOn::Primary => (is_primary, is_primary),
+            ShowOn::ClockOnSecondary => (true, is_primary),
+        };
+        let mut elements: Vec<(OverlayElement, &dyn Drawable)> = Vec::new();
+        for kind in &self.config.overlay.order {
+            match kind {
+                OverlayElement::Clock if self.config.show_clock && show_clock => {
+                    elements.push((*kind, self.clock))
+                }
+                OverlayElement::Indicator if self.config.show_indicator && show_indicator => {
+                    elements.push((*kind, self.indicator))
+                }
+                // Go along with the indicator, being about typing too
+                OverlayElement::Modifiers if self.config.show_modifiers && show_indicator => {
+                    elements.push((*kind, self.modifier_badges))
+                }
+                // Decorates the clock, so it is shown where the clock is
+                OverlayElement::Avatar
+                    if self.config.show_avatar && self.avatar.image.is_some() && show_clock =>
+                {
+                    elements.push((*kind, self.avatar))
+                }
+                _ => {}
+            }
+        }
+        elements
+    }
+
+    /// Vertical offset of each element from its own position, so that with
+    /// the stacked layout they form a centered column keeping their
+    /// top-to-bottom order
+    fn layout_offsets(
+        &self,
+        context: &cairo::Context,
+        elements: &[(OverlayElement, &dyn Drawable)],
+        width: i32,
+        height: i32,
+        state: &DrawContext,
+    ) -> Vec<f64> {
+        let mut offsets = vec![0.0; elements.len()];
+        if self.config.layout == Layout::Free || elements.is_empty() {
+            return offsets;
+        }
+
+        let bounds = elements
+            .iter()
+            .map(|(kind, element)| {
+                self.element_bounds(context, *kind, *element, width, height, state)
+            })
+            .collect::<Vec<_>>();
+        let mut rows = (0..elements.len()).collect::<Vec<_>>();
+        rows.sort_by(|&a, &b| bounds[a].y().total_cmp(&bounds[b].y()));
+
+        let spacing = self.config.layout_spacing;
+        let total =
+            bounds.iter().map(|b| b.height()).sum::<f64>() + spacing * (elements.len() - 1) as f64;
+        let mut y = (height as f64 - total) / 2.0;
+        for row in rows {
+            offsets[row] = y - bounds[row].y();
+            y += bounds[row].height() + spacing;
+        }
+        offsets
+    }
+
+    /// Paints the state tint and obscuring layer, then the card and the
+    /// elements of `overlay.order` that `show_on` allows on this output.
+    /// Everything but `is_primary`, `is_focused` and `output_scale` is shared
+    /// by all outputs, so they show the same state
+    pub fn draw_overlays(
+        &self,
+        context: &cairo::Context,
+        width: i32,
+        height: i32,
+        is_primary: bool,
+        is_focused: bool,
+        output_scale: i32,
+    ) {
+        // Nothing is drawn outside the surface, however large the elements
+        context.save().unwrap();
+        context.rectangle(0.0, 0.0, width as f64, height as f64);
+        context.clip();
+
+        // Overlays are redrawn on every frame, so the tint follows the state
+        // without redrawing the background
+        let tint = &self.config.background_tint_on_state;
+        if tint.enabled {
+            let color = if self.indicator.auth_state == AuthState::Validating {
+                Some(&tint.verifying)
+            } else if self.indicator.auth_state == AuthState::Invalid {
+                Some(&tint.wrong)
+            } else if self.indicator.input_state == InputState::Clear {
+                Some(&tint.cleared)
+            } else {
+                None
+            };
+            if let Some(color) = color {
+                context.set_source_color(color);
+                context.paint().unwrap();
+            }
+        }
+
+        // Progressively obscure the screen on failed attempts
+        let obscure = &self.config.escalate_obscure_on_failure;
+        let state = self.draw_context(is_focused, output_scale);
+        if obscure.enabled && state.failed_attempts > 0 {
+            let alpha = (obscure.step * state.failed_attempts as f64).min(obscure.max);
+            context.set_source_rgba(0.0, 0.0, 0.0, alpha);
+            context.paint().unwrap();
+        }
+
+        context.save().unwrap();
+        let (jitter_x, jitter_y) = self.jitter_offset();
+        context.translate(jitter_x, jitter_y);
+        let elements = self.elements(is_primary);
+        let offsets = self.layout_offsets(context, &elements, width, height, &state);
+        if self.config.overlay_card.enabled {
+            let bounds = elements
+                .iter()
+                .zip(&offsets)
+                .map(|((kind, element), offset)| {
+                    let b = self.element_bounds(context, *kind, *element, width, height, &state);
+                    cairo::Rectangle::new(b.x(), b.y() + offset, b.width(), b.height())
+                })
+                .collect::<Vec<_>>();
+            self.overlay_card.draw(context, &bounds, 1.0);
+        }
+        for ((kind, element), offset) in elements.iter().zip(&offsets) {
+            context.save().unwrap();
+            context.translate(0.0, *offset);
+            shrink_about_center(context, self.fit_scale(*kind, width, height), width, height);
+            element.draw(context, width, height, 1.0, &state);
+            context.restore().unwrap();
+        }
+        if let Some(offset) = self.pin_pad_offset(&elements, &offsets) {
+            context.save().unwrap();
+            context.translate(0.0, offset);
+            let fit = self.fit_scale(OverlayElement::Indicator, width, height);
+            shrink_about_center(context, fit, width, height);
+            self.indicator.draw_pin_pad(context, width, height, 1.0);
+            context.restore().unwrap();
+        }
+        context.restore().unwrap();
+        context.restore().unwrap();
+    }
+
+    /// Factor an element is shrunk by to fit the surface. Only the indicator
+    /// is placed relative to its size, the others scale with their font
+    fn fit_scale(&self, kind: OverlayElement, width: i32, height: i32) -> f64 {
+        match kind {
+            OverlayElement::Indicator => self.indicator.fit_scale(width, height),
+            _ => 1.0,
+        }
+    }
+
+    /// What the elements draw with on an output
+    fn draw_context(&self, is_focused: bool, output_scale: i32) -> DrawContext<'_> {
+        DrawContext {
+            keyboard: self.keyboard,
+            failed_attempts: self.indicator.failed_attempts.value(),
+            is_focused,
+            has_pin_pad: self.shows_pin_pad(),
+            layout_names: &self.config.keyboard.layout_names,
+            font_scale: match self.config.font_scaling {
+                FontScaling::Logical => 1.0,
+                FontScaling::Physical => output_scale.max(1) as f64,
+            },
+        }
+    }
+
+    /// Area an element covers as drawn, after `fit_scale`
+    fn element_bounds(
+        &self,
+        context: &cairo::Context,
+        kind: OverlayElement,
+        element: &dyn Drawable,
+        width: i32,
+        height: i32,
+        state: &DrawContext,
+    ) -> cairo::Rectangle {
+        let b = element.bounds(context, width, height, 1.0, state);
+        let fit = self.fit_scale(kind, width, height);
+        let (xc, yc) = (width as f64 / 2.0, height as f64 / 2.0);
+        cairo::Rectangle::new(
+            xc + (b.x() - xc) * fit,
+            yc + (b.y() - yc) * fit,
+            b.width() * fit,
+            b.height() * fit,
+        )
+    }
+
+    /// Whether touch input goes through the PIN pad: when configured, or as
+    /// the only way to type without a keyboard
+    fn shows_pin_pad(&self) -> bool {
+        let fallback = self.config.pin_pad_fallback && !self.keyboard.is_present();
+        self.has_touch && (self.config.show_pin_pad || fallback)
+    }
+
+    /// Layout offset of the PIN pad, which follows the indicator, if shown
+    fn pin_pad_offset(
+        &self,
+        elements: &[(OverlayElement, &dyn Drawable)],
+        offsets: &[f64],
+    ) -> Option<f64> {
+        if !self.shows_pin_pad() || self.indicator.is_asleep {
+            return None;
+        }
+        let index = elements
+            .iter()
+            .position(|(kind, _)| *kind == OverlayElement::Indicator)?;
+        Some(offsets[index])
+    }
+
+    /// PIN pad key at `(x, y)` of a surface, mirroring how `draw_overlays`
+    /// places the pad
+    pub fn pin_pad_key_at(
+        &self,
+        x: f64,
+        y: f64,
+        width: i32,
+        height: i32,
+        is_primary: bool,
+        output_scale: i32,
+    ) -> Option<PinKey> {
+        // Only measures text for the stacked layout
+        let surface = cairo::ImageSurface::create(cairo::Format::ARgb32, 1, 1).ok()?;
+        let context = cairo::Context::new(&surface).ok()?;
+        let elements = self.elements(is_primary);
+        let state = self.draw_context(true, output_scale);
+        let offsets = self.layout_offsets(&context, &elements, width, height, &state);
+        let offset = self.pin_pad_offset(&elements, &offsets)?;
+        let (jitter_x, jitter_y) = self.jitter_offset();
+        // Undo the shrinking of the indicator and its pad
+        let fit = self.fit_scale(OverlayElement::Indicator, width, height);
+        let (xc, yc) = (width as f64 / 2.0, height as f64 / 2.0);
+        let x = xc + (x - jitter_x - xc) / fit;
+        let y = yc + (y - jitter_y - offset - yc) / fit;
+        self.indicator.pin_pad_key_at(x, y, width, height, 1.0)
+    }
+
+    /// Slowly wandering offset of the overlays within `indicator.jitter`
+    /// pixels, so nothing stays at the same place on OLED panels
+    fn jitter_offset(&self) -> (f64, f64) {
+        let radius = self.config.indicator.jitter;
+        if radius <= 0.0 {
+            return (0.0, 0.0);
+        }
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        // Periods of several minutes, coprime so the path rarely repeats
+        let angle = secs * TAU / 600.0;
+        let distance = radius * (secs * TAU / 437.0).sin();
+        (distance * angle.cos(), distance * angle.sin())
+    }
+}
+
+/// Scales what is drawn next by `fit` about the surface center
+fn shrink_about_center(context: &cairo::Context, fit: f64, width: i32, height: i32) {
+    if fit < 1.0 {
+        let (xc, yc) = (width as f64 / 2.0, height as f64 / 2.0);
+        context.translate(xc, yc);
+        context.scale(fit, fit);
+        context.translate(-xc, -yc);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+    use crate::overlay::InputState;
+
+    /// What a scene borrows, for an indicator just after a key press
+    struct Parts {
+        config: Config,
+        indicator: Indicator,
+        clock: Clock,
+        overlay_card: OverlayCard,
+        modifier_badges: ModifierBadges,
+        avatar: Avatar,
+        keyboard: KeyboardState,
+    }
+
+    impl Parts {
+        fn typing(user_config: &str) -> Self {
+            let user_config = user_config.parse::<toml::Table>().unwrap();
+            let merged = Config::merge_config_with_defaults(user_config);
+            let config = Config::deserialize(merged).unwrap();
+            let mut indicator = Indicator::new(config.indicator.clone());
+            indicator.input_state = InputState::Letter;
+            indicator.highlight_start = 700;
+            Self {
+                indicator,
+                clock: Clock::new(config.clock.clone()),
+                overlay_card: OverlayCard {
+                    config: config.overlay_card.clone(),
+                },
+                modifier_badges: ModifierBadges {
+                    config: config.modifiers.clone(),
+                },
+                avatar: Avatar {
+                    config: config.avatar.clone(),
+                    image: None,
+                },
+                keyboard: KeyboardState::new(None),
+                config,
+            }
+        }
+
+        fn scene(&self) -> Scene<'_> {
+            Scene {
+                config: &self.config,
+                background_image: None,
+                background_mode: self.config.background_mode,
+                crossfade_from: None,
+                indicator: &self.indicator,
+                clock: &self.clock,
+                overlay_card: &self.overlay_card,
+                modifier_badges: &self.modifier_badges,
+                avatar: &self.avatar,
+                keyboard: &self.keyboard,
+                has_touch: false,
+            }
+        }
+
+        /// Pixels of the overlays of an output
+        fn draw(&self, is_primary: bool, is_focused: bool) -> Vec<u8> {
+            self.draw_sized(400, 600, is_primary, is_focused)
+        }
+
+        fn draw_sized(
+            &self,
+            width: i32,
+            height: i32,
+            is_primary: bool,
+            is_focused: bool,
+        ) -> Vec<u8> {
+            let mut surface =
+                cairo::ImageSurface::create(cairo::Format::ARgb32, width, height).unwrap();
+            {
+                let context = cairo::Context::new(&surface).unwrap();
+                self.scene()
+                    .draw_overlays(&context, width, height, is_primary, is_focused, 1);
+            }
+            surface.data().unwrap().to_vec()
+        }
+    }
+
+    // No clock, which could tick between the draws
+    const NO_CLOCK: &str = "show_clock = false\n";
+
+    #[test]
+    fn outputs_show_the_same_key_press() {
+        let parts = Parts::typing(NO_CLOCK);
+        assert!(parts.draw(true, true) == parts.draw(false, false));
+    }
+
+    #[test]
+    fn focused_feedback_only_highlights_the_focused_output() {
+        let parts = Parts::typing(&format!("{NO_CLOCK}[indicator]\nfeedback_on = \"focused\""));
+        let mut neutral = Parts::typing(NO_CLOCK);
+        neutral.indicator.input_state = InputState::Neutral;
+
+        assert!(parts.draw(true, true) != parts.draw(false, false));
+        assert!(parts.draw(false, false) == neutral.draw(false, false));
+    }
+
+    #[test]
+    fn tiny_surfaces_show_a_shrunk_indicator() {
+        let parts = Parts::typing(NO_CLOCK);
+        // Degenerate sizes mustn't panic
+        parts.draw_sized(1, 1, true, true);
+        parts.draw_sized(300, 2, true, true);
+
+        // The default ring sits 150px below the center, off this surface
+        // unless shrunk to fit
+        let pixels = parts.draw_sized(60, 60, true, true);
+        let drawn = pixels
+            .chunks_exact(4)
+            .filter(|pixel| pixel.iter().any(|b| *b != 0));
+        assert!(drawn.count() > 100);
+    }
+}
@@ -0,0 +1,235 @@
+//! Screenshots of the outputs for `background_mode = "screenshot"`.
+//!
+//! Outputs are copied with wlr-screencopy before locking, as the compositor
+//! hides the session once locked. Each lock surface then shows the blurred
+//! screenshot of its output, outputs connected later the background color.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use log::{error, warn};
+use smithay_client_toolkit::shm::{
+    Shm,
+    slot::{Buffer, SlotPool},
+};
+use wayland_client::{
+    Connection, Dispatch, Proxy, QueueHandle, WEnum,
+    backend::ObjectId,
+    delegate_noop,
+    globals::GlobalList,
+    protocol::{wl_output, wl_shm},
+};
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use crate::{State, blur};
+
+/// How long locking waits for the compositor to copy the outputs, those not
+/// copied by then show the background color
+pub const CAPTURE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Output being copied into a shm buffer
+struct Capture {
+    frame: ZwlrScreencopyFrameV1,
+    /// Format, size and stride of the buffer, once the compositor offered a
+    /// format we can read
+    layout: Option<(wl_shm::Format, i32, i32, i32)>,
+    buffer: Option<(SlotPool, Buffer)>,
+    y_invert: bool,
+}
+
+#[derive(Default)]
+pub struct Screenshots {
+    pending: HashMap<ObjectId, Capture>,
+    taken: HashMap<ObjectId, cairo::ImageSurface>,
+}
+
+impl Screenshots {
+    /// Whether some outputs are still being copied
+    pub fn is_capturing(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Screenshot of `output`, if it was taken
+    pub fn take(&mut self, output: &wl_output::WlOutput) -> Option<cairo::ImageSurface> {
+        self.taken.remove(&output.id())
+    }
+}
+
+impl State {
+    /// Starts copying every output, dispatch until `is_capturing` is false
+    /// before locking
+    pub fn capture_screenshots(&mut self, globals: &GlobalList, qh: &QueueHandle<Self>) {
+        let manager = match globals.bind::<ZwlrScreencopyManagerV1, _, _>(qh, 1..=3, ()) {
+            Ok(manager) => manager,
+            Err(err) => {
+                error!(
+                    "Screenshots need zwlr_screencopy_manager_v1 ({err}), using the background color"
+                );
+                return;
+            }
+        };
+        if self.config.blur_sigma <= 0.0 {
+            warn!("blur_sigma is 0, the lock screen shows the screen contents unblurred");
+        }
+        for output in self.output_state.outputs() {
+            let frame = manager.capture_output(0, &output, qh, output.id());
+            let capture = Capture {
+                frame,
+                layout: None,
+                buffer: None,
+                y_invert: false,
+            };
+            self.screenshots.pending.insert(output.id(), capture);
+        }
+        // Frames outlive the manager
+        manager.destroy();
+    }
+
+    fn finish_capture(&mut self, output_id: &ObjectId) {
+        let Some(capture) = self.screenshots.pending.remove(output_id) else {
+            return;
+        };
+        capture.frame.destroy();
+        match capture.into_image() {
+            Some(mut image) => {
+                // Three box blurs of radius r deviate by about r
+                blur::blur(&mut image, (self.config.blur_sigma * 2.0).round() as usize);
+                self.screenshots.taken.insert(output_id.clone(), image);
+            }
+            None => warn!("Failed to read the screenshot of output {output_id}"),
+        }
+    }
+
+    /// Gives up on the outputs not copied yet, after [`CAPTURE_TIMEOUT`]
+    pub fn fail_pending_captures(&mut self) {
+        let pending: Vec<_> = self.screenshots.pending.keys().cloned().collect();
+        for output_id in pending {
+            self.fail_capture(&output_id);
+        }
+    }
+
+    fn fail_capture(&mut self, output_id: &ObjectId) {
+        if let Some(capture) = self.screenshots.pending.remove(output_id) {
+            capture.frame.destroy();
+            warn!("Failed to take a screenshot of output {output_id}, using the background color");
+        }
+    }
+}
+
+impl Capture {
+    /// Size in bytes of the buffer, if the layout fits the image
+    fn buffer_len(&self) -> Option<usize> {
+        let (_, width, height, stride) = self.layout?;
+        let (width, height, stride) = (
+            usize::try_from(width).ok()?,
+            usize::try_from(height).ok()?,
+            usize::try_from(stride).ok()?,
+        );
+        if width == 0 || height == 0 || stride < width.checked_mul(4)? {
+            return None;
+        }
+        stride.checked_mul(height)
+    }
+
+    /// Asks the compositor to copy the output into a new buffer
+    fn copy(&mut self, shm: &Shm) -> bool {
+        let Some(len) = self.buffer_len() else {
+            warn!("Unusable screenshot layout {:?}", self.layout);
+            return false;
+        };
+        let Some((format, width, height, stride)) = self.layout else {
+            return false;
+        };
+        let Ok(mut pool) = SlotPool::new(len, shm) else {
+            return false;
+        };
+        let Ok((buffer, _)) = pool.create_buffer(width, height, stride, format) else {
+            return false;
+        };
+        self.frame.copy(buffer.wl_buffer());
+        self.buffer = Some((pool, buffer));
+        true
+    }
+
+    /// Copied pixels, upright. The screen is opaque, any alpha is ignored
+    fn into_image(self) -> Option<cairo::ImageSurface> {
+        let len = self.buffer_len()?;
+        let (_, width, height, stride) = self.layout?;
+        let (mut pool, buffer) = self.buffer?;
+        let data = buffer.canvas(&mut pool)?;
+        if data.len() < len {
+            return None;
+        }
+        let mut image = cairo::ImageSurface::create(cairo::Format::Rgb24, width, height).ok()?;
+        let image_stride = image.stride() as usize;
+        let (width, height, stride) = (width as usize, height as usize, stride as usize);
+        {
+            let mut pixels = image.data().ok()?;
+            for y in 0..height {
+                let source_y = if self.y_invert { height - 1 - y } else { y };
+                pixels[y * image_stride..][..width * 4]
+                    .copy_from_slice(&data[source_y * stride..][..width * 4]);
+            }
+        }
+        Some(image)
+    }
+}
+
+impl Dispatch<ZwlrScreencopyFrameV1, ObjectId> for State {
+    fn event(
+        state: &mut Self,
+        frame: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        output_id: &ObjectId,
+        _conn: &Connection,
+        _qh: &QueueHandle<Self>,
+    ) {
+        let Some(capture) = state.screenshots.pending.get_mut(output_id) else {
+            return;
+        };
+        match event {
+            // Both have cairo's pixel layout
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format: WEnum::Value(format @ (wl_shm::Format::Xrgb8888 | wl_shm::Format::Argb8888)),
+                width,
+                height,
+                stride,
+            } => {
+                capture.layout = match (
+                    i32::try_from(width),
+                    i32::try_from(height),
+                    i32::try_from(stride),
+                ) {
+                    (Ok(width), Ok(height), Ok(stride)) => Some((format, width, height, stride)),
+                    _ => None,
+                };
+                // Before version 3, formats aren't followed by `buffer_done`
+                if frame.version() < 3
+                    && capture.buffer.is_none()
+                    && !capture.copy(&state.shm_state)
+                {
+                    state.fail_capture(output_id);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => {
+                let copying = capture.buffer.is_some() || capture.copy(&state.shm_state);
+                if !copying {
+                    state.fail_capture(output_id);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::Flags {
+                flags: WEnum::Value(flags),
+            } => {
+                capture.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert);
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => state.finish_capture(output_id),
+            zwlr_screencopy_frame_v1::Event::Failed => state.fail_capture(output_id),
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(State: ignore ZwlrScreencopyManagerV1);
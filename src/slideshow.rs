@@ -0,0 +1,87 @@
+//! Rotates the background through `background_slideshow` on a timer, with an
+//! optional crossfade between images
+
+use std::time::{Duration, Instant};
+
+use log::error;
+use smithay_client_toolkit::reexports::calloop::timer::{TimeoutAction, Timer};
+
+use crate::{State, background_image::try_load_image};
+
+#[derive(Default)]
+pub struct Slideshow {
+    /// Index in `background_slideshow` of the shown image
+    current: usize,
+    /// Image being faded out and when the crossfade started
+    crossfade_from: Option<(cairo::ImageSurface, Instant)>,
+}
+
+impl Slideshow {
+    pub fn is_crossfading(&self) -> bool {
+        self.crossfade_from.is_some()
+    }
+
+    /// Previous image and how far the new one has faded in (0..1), while a
+    /// crossfade is running
+    pub fn crossfade(&self, duration: Duration) -> Option<(&cairo::ImageSurface, f64)> {
+        let (image, start) = self.crossfade_from.as_ref()?;
+        let progress = start.elapsed().as_secs_f64() / duration.as_secs_f64();
+        Some((image, progress.min(1.0)))
+    }
+}
+
+impl State {
+    /// Starts rotating the background, the first image is already loaded as
+    /// the background image. Lists of less than two images never rotate.
+    pub fn create_slideshow(&self) {
+        if self.config.background_slideshow.len() < 2 || !self.config.background_mode.shows_images()
+        {
+            return;
+        }
+        let interval = Duration::from_secs(self.config.slideshow_interval_secs.max(1));
+        self.loop_handle
+            .insert_source(Timer::from_duration(interval), move |_, _, state| {
+                state.advance_slideshow();
+                TimeoutAction::ToDuration(interval)
+            })
+            .expect("Failed to insert slideshow timer");
+    }
+
+    fn advance_slideshow(&mut self) {
+        let paths = &self.config.background_slideshow;
+        // Skip over images that fail to load, keeping the current one if all do
+        for step in 1..paths.len() {
+            let index = (self.slideshow.current + step) % paths.len();
+            let image = match try_load_image(&paths[index], self.config.max_image_pixels) {
+                Ok(image) => image,
+                Err(err) => {
+                    error!(
+                        "Failed to load slideshow image {} with error {err}",
+                        paths[index]
+                    );
+                    continue;
+                }
+            };
+            self.slideshow.current = index;
+            let previous = self.background_image.replace(image);
+            if self.config.slideshow_crossfade_ms > 0 {
+                self.slideshow.crossfade_from = previous.map(|image| (image, Instant::now()));
+            }
+            self.invalidate_backgrounds();
+            return;
+        }
+    }
+
+    /// Ends a finished crossfade, redrawing the backgrounds once more without it
+    pub fn update_slideshow_crossfade(&mut self) {
+        let duration = Duration::from_millis(self.config.slideshow_crossfade_ms);
+        if self
+            .slideshow
+            .crossfade(duration)
+            .is_some_and(|(_, progress)| progress >= 1.0)
+        {
+            self.slideshow.crossfade_from = None;
+            self.invalidate_backgrounds();
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Publishes the lock state on a Unix socket for bars and other tools.
+//!
+//! Every connected client gets the current state on connect and then a line
+//! per change, formatted as `<state> <failed attempts>` where the state is one
+//! of `locked`, `verifying`, `failed`, `locked_out` or `unlocked`. Nothing
+//! about the password, not even its length, is ever sent.
+
+use std::io::Write;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use log::error;
+use smithay_client_toolkit::reexports::calloop::{Interest, Mode, PostAction, generic::Generic};
+
+use crate::{LifeCycle, State, overlay::AuthState};
+
+#[derive(Default)]
+pub struct StatusSocket {
+    path: Option<PathBuf>,
+    clients: Vec<UnixStream>,
+    /// Last line sent, to only publish changes
+    current: String,
+}
+
+impl StatusSocket {
+    fn send(&mut self, line: &str) {
+        self.clients
+            .retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+    }
+}
+
+impl Drop for StatusSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+impl State {
+    pub fn create_status_socket(&mut self) {
+        let Some(path) = self.config.status_socket_path.clone() else {
+            return;
+        };
+        // A previous instance that crashed may have left its socket behind,
+        // anything else at the path is not ours to remove
+        match std::fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                if let Err(err) = std::fs::remove_file(&path) {
+                    error!("Failed to remove the stale status socket {path} with error {err}");
+                    return;
+                }
+            }
+            Ok(_) => {
+                error!("Not creating the status socket {path} over an existing file");
+                return;
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+            Err(err) => {
+                error!("Failed to check the status socket path {path} with error {err}");
+                return;
+            }
+        }
+        // Created accessible to the user only, as other users could connect
+        // in between if it were restricted after binding
+        // SAFETY: umask only swaps the process file mode mask
+        let umask = unsafe { libc::umask(0o177) };
+        let listener = UnixListener::bind(&path);
+        unsafe { libc::umask(umask) };
+        let listener = match listener {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to create the status socket {path} with error {err}");
+                return;
+            }
+        };
+        self.status_socket.path = Some(PathBuf::from(&path));
+        if let Err(err) = listener.set_nonblocking(true) {
+            error!("Failed to create the status socket {path} with error {err}");
+            return;
+        }
+
+        let source = Generic::new(listener, Interest::READ, Mode::Level);
+        let result = self
+            .loop_handle
+            .insert_source(source, |_, listener, state| {
+                loop {
+                    match listener.accept() {
+                        Ok((client, _)) => state.add_status_client(client),
+                        Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => break,
+                        Err(err) => {
+                            error!("Failed to accept a status client with error {err}");
+                            break;
+                        }
+                    }
+                }
+                Ok(PostAction::Continue)
+            });
+        if let Err(err) = result {
+            error!("Failed to create the status socket {path} with error {err}");
+        }
+    }
+
+    fn add_status_client(&mut self, mut client: UnixStream) {
+        // Slow readers are dropped rather than blocking the lock
+        if client.set_nonblocking(true).is_err() {
+            return;
+        }
+        let line = self.status_line();
+        if client.write_all(line.as_bytes()).is_ok() {
+            self.status_socket.clients.push(client);
+        }
+    }
+
+    fn status_line(&self) -> String {
+        let status = match self.lifecycle {
+            LifeCycle::Initing | LifeCycle::Locked => {
+                if self.indicator.lockout_remaining().is_some() {
+                    "locked_out"
+                } else if self.indicator.auth_state == AuthState::Validating {
+                    "verifying"
+                } else if self.indicator.auth_state == AuthState::Invalid {
+                    "failed"
+                } else {
+                    "locked"
+                }
+            }
+            LifeCycle::FadingOut(_) | LifeCycle::Authenticated | LifeCycle::Ended => "unlocked",
+        };
+        format!("{status} {}\n", self.indicator.failed_attempts.value())
+    }
+
+    /// Sends the state to the status clients if it changed
+    pub fn publish_status(&mut self) {
+        if self.status_socket.path.is_none() {
+            return;
+        }
+        let line = self.status_line();
+        if line != self.status_socket.current {
+            self.status_socket.send(&line);
+            self.status_socket.current = line;
+        }
+    }
+}
@@ -0,0 +1,118 @@
+//! Reloads the theme on SIGUSR2 or SIGHUP: the colors, fonts, sizes and visible
+//! elements of the config file. Authentication and behavior stay as they
+//! were when locking.
+//!
+//! The background image is only decoded again when its path or mode changed,
+//! so tweaking colors over a large wallpaper doesn't flicker or stall. An
+//! invalid config keeps the current theme.
+
+use std::sync::atomic::Ordering;
+
+use log::{error, info};
+
+use crate::{
+    State,
+    avatar::load_avatar,
+    background_image::{configured_image_path, try_load_image},
+    config::Config,
+    overlay::{Avatar, Clock},
+    read_config_str,
+};
+
+impl State {
+    pub fn create_theme_reload_handler(&self) {
+        const SIGHUP: i32 = 1;
+        const SIGUSR2: i32 = 12;
+        for (signal, name) in [(SIGUSR2, "SIGUSR2"), (SIGHUP, "SIGHUP")] {
+            if let Err(err) = signal_hook::flag::register(signal, self.reload_requested.clone()) {
+                error!("Failed to register {name} handling with {err}");
+            }
+        }
+    }
+
+    pub fn reload_theme_if_requested(&mut self) {
+        if self.reload_requested.swap(false, Ordering::Relaxed) {
+            self.reload_theme();
+        }
+    }
+
+    /// Decodes the newly configured background, keeping the current one if
+    /// it can't be, as the session stays locked whatever the config says
+    fn reload_configured_image(&mut self) {
+        let Some(path) = configured_image_path(&self.config) else {
+            self.background_image = None;
+            return;
+        };
+        match try_load_image(path, self.config.max_image_pixels) {
+            Ok(image) => self.background_image = Some(image),
+            Err(err) => {
+                error!("Failed to reload {path} with error {err}, keeping the previous image")
+            }
+        }
+    }
+
+    pub fn reload_theme(&mut self) {
+        self.redraw_requested = true;
+        let new = match Config::parse(&read_config_str()) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Failed to reload the theme with error {err}, keeping the current one");
+                return;
+            }
+        };
+        let old = &self.config;
+        // The slideshow keeps showing its own images
+        let image_changed = old.background_slideshow.is_empty()
+            && (new.background_image != old.background_image
+                || new.background_mode != old.background_mode);
+        let avatar_changed =
+            new.show_avatar != old.show_avatar || new.avatar.path != old.avatar.path;
+
+        let config = &mut self.config;
+        config.background_color = new.background_color;
+        config.background_image = new.background_image;
+        config.background_mode = new.background_mode;
+        config.background_filter = new.background_filter;
+        config.dim = new.dim;
+        config.dim_color = new.dim_color;
+        config.border = new.border;
+        config.clock = new.clock;
+        config.indicator = new.indicator;
+        config.overlay = new.overlay;
+        config.layout = new.layout;
+        config.layout_spacing = new.layout_spacing;
+        config.font_scaling = new.font_scaling;
+        config.overlay_card = new.overlay_card;
+        config.modifiers = new.modifiers;
+        config.avatar = new.avatar;
+        config.escalate_obscure_on_failure = new.escalate_obscure_on_failure;
+        config.background_tint_on_state = new.background_tint_on_state;
+        config.show_clock = new.show_clock;
+        config.show_indicator = new.show_indicator;
+        config.show_modifiers = new.show_modifiers;
+        config.show_avatar = new.show_avatar;
+
+        self.indicator.config = self.config.indicator.clone();
+        self.clock = Clock::new(self.config.clock.clone());
+        self.overlay_card.config = self.config.overlay_card.clone();
+        self.modifier_badges.config = self.config.modifiers.clone();
+        if avatar_changed {
+            self.avatar = Avatar {
+                config: self.config.avatar.clone(),
+                image: self
+                    .config
+                    .show_avatar
+                    .then(|| load_avatar(&self.config))
+                    .flatten(),
+            };
+        } else {
+            self.avatar.config = self.config.avatar.clone();
+        }
+        if image_changed {
+            self.reload_configured_image();
+        }
+        // Repainted from the decoded image for the new color, dim or border
+        self.invalidate_backgrounds();
+        info!("Reloaded the theme, background image reloaded: {image_changed}");
+    }
+}